@@ -1,4 +1,11 @@
 #![allow(dead_code, unused_variables)]
+pub mod diagnostic;
 pub mod emitter;
+pub mod error;
+pub mod interpreter;
 pub mod lexer;
 pub mod parser;
+pub mod serialize;
+#[cfg(test)]
+mod testing;
+pub mod types;
@@ -0,0 +1,244 @@
+use crate::compiler::error::CompileError;
+use std::error::Error;
+use std::fmt;
+
+/// A region of the source code: a byte range, plus the line and column it starts on.
+/// Lines and columns are 1-based; columns count characters rather than bytes.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: u32, col: u32) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+
+    /// Span running from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message attached to a location in the source, ready to be shown to the user.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    /// Formats the diagnostic with its location and an excerpt of the offending line, e.g.
+    ///
+    /// ```text
+    /// error: Expected newline after 'LET' statement
+    ///  --> fib.tbsc:4:11
+    ///   |
+    /// 4 | LET a = 0 b
+    ///   |           ^
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let span = self.span;
+        let line_text = source
+            .lines()
+            .nth(span.line.saturating_sub(1) as usize)
+            .unwrap_or("");
+        let line_num = span.line.to_string();
+        let gutter = " ".repeat(line_num.len());
+
+        // Count the column and the width of the span in characters of the source, rather
+        // than trusting a column worked out without it.
+        let col = source
+            .get(..span.start)
+            .and_then(|before| before.rsplit('\n').next())
+            .map_or(span.col, |before| before.chars().count() as u32 + 1);
+
+        // Underline the span, but never past the end of the line it starts on.
+        let leading = col.saturating_sub(1) as usize;
+        let available = line_text.chars().count().saturating_sub(leading);
+        let width = source
+            .get(span.start..span.end)
+            .map(|text| text.chars().take_while(|chr| *chr != '\n').count())
+            .unwrap_or(0)
+            .min(available)
+            .max(1);
+
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        out.push_str(&format!("{gutter}--> {file_name}:{}:{col}\n", span.line));
+        out.push_str(&format!("{gutter} |\n"));
+        out.push_str(&format!("{line_num} | {line_text}\n"));
+        // Pad with the same tabs as the line, so that the carets line up however wide
+        // the terminal shows them.
+        let padding: String = line_text
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(leading)
+            .map(|chr| if chr == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("{gutter} | {padding}{}\n", "^".repeat(width)));
+        if let Some(help) = &self.help {
+            out.push_str(&format!("{gutter} = help: {help}\n"));
+        }
+        out
+    }
+}
+
+/// Why a program failed to compile: every error found in it, along with any warnings.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Diagnostics {
+    pub errors: Vec<CompileError>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Formats the warnings and then the errors for the terminal, each with an excerpt of
+    /// `source` where it has a span.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = String::new();
+        for warning in &self.warnings {
+            out.push_str(&warning.render(file_name, source));
+        }
+        for err in &self.errors {
+            out.push_str(&err.render(file_name, source));
+        }
+        if self.errors.len() > 1 {
+            out.push_str(&format!(
+                "error: aborting due to {} previous errors\n",
+                self.errors.len()
+            ));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, err) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+impl From<CompileError> for Diagnostics {
+    fn from(err: CompileError) -> Diagnostics {
+        Diagnostics {
+            errors: vec![err],
+            warnings: Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    let source = "LET a = 0\nLET b = 1 c\n";
+    let diagnostic = Diagnostic::new("Expected newline", Span::new(20, 21, 2, 11));
+    assert_eq!(
+        diagnostic.render("fib.tbsc", source),
+        "error: Expected newline\n \
+         --> fib.tbsc:2:11\n  \
+         |\n\
+         2 | LET b = 1 c\n  \
+         |           ^\n"
+    );
+}
+
+#[test]
+fn test_render_clamps_to_line() {
+    let source = "PRINT \"oops\n";
+    let diagnostic = Diagnostic::new("unterminated", Span::new(6, 12, 1, 7));
+    let rendered = diagnostic.render("<code>", source);
+    assert!(rendered.ends_with("1 | PRINT \"oops\n  |       ^^^^^\n"));
+}
+
+#[test]
+fn test_render_tabs() {
+    let source = "IF 1 > 0 THEN\n\t\tPRINT zz\nENDIF\n";
+    let diagnostic = Diagnostic::new("Undeclared identifier 'zz'", Span::new(22, 24, 2, 9));
+    assert!(diagnostic
+        .render("tabs.tbsc", source)
+        .ends_with("2 | \t\tPRINT zz\n  | \t\t      ^^\n"));
+}
+
+#[test]
+fn test_render_warning() {
+    let diagnostic = Diagnostic::warning("Label 'top' is never used", Span::new(6, 9, 1, 7));
+    assert!(diagnostic
+        .render("labels.tbsc", "LABEL top\n")
+        .starts_with("warning: Label 'top' is never used\n"));
+}
+
+#[test]
+fn test_render_diagnostics() {
+    let source = "PRINT x\nLABEL top\n";
+    let diagnostics = Diagnostics {
+        errors: vec![
+            CompileError::semantic("Undeclared identifier 'x'", Span::new(6, 7, 1, 7)),
+            CompileError::backend("no C compiler found"),
+        ],
+        warnings: vec![Diagnostic::warning(
+            "Label 'top' is never used",
+            Span::new(14, 17, 2, 7),
+        )],
+    };
+    let rendered = diagnostics.render("<code>", source);
+    assert!(rendered.starts_with("warning: Label 'top' is never used\n"));
+    assert!(rendered.contains("error: Undeclared identifier 'x'\n"));
+    assert!(rendered
+        .ends_with("error: no C compiler found\nerror: aborting due to 2 previous errors\n"));
+    assert_eq!(
+        diagnostics.to_string(),
+        "semantic error at 1:7: Undeclared identifier 'x'\nbackend error: no C compiler found"
+    );
+}
@@ -1,9 +1,285 @@
+#[cfg(test)]
+use crate::compiler::testing::{build_dialect, build_source};
+use crate::compiler::types::{
+    expression_type, is_string_expression, return_key, term_type, unary_type, Type,
+};
 use crate::lexer::Token;
 use crate::parser::{
-    Comparison, Expression, Primary, PrintMessage, Program, Statement, Term, TermComp, Unary,
+    is_line_number, Comparison, Condition, ExprComp, Expression, Function, Primary, PrintItem,
+    Program, Statement, Term, TermComp, Unary, CALL_DEPTH, GOSUB_DEPTH, PRINT_ZONE_WIDTH,
 };
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
+/// Support code for PRINT, string variables, GOSUB and functions.
+///
+/// PRINT output goes through `tb_write`, which keeps track of the column so that `,` can
+/// tab to the next print zone.
+///
+/// Every string expression evaluates to a fresh heap copy, which whatever consumes it
+/// (an assignment, a comparison, PRINT or another concatenation) takes ownership of
+/// and frees.
+///
+/// GOSUB pushes its line number, which RETURN pops and jumps back to through the switch
+/// at the end of `main`.
+///
+/// Every function counts itself in and out of `tb_call_depth`, so that runaway recursion
+/// stops with an error rather than a crash.
+const RUNTIME: &str = r#"/* Not every program needs every helper. */
+#if defined(__GNUC__) || defined(__TINYC__)
+#define TB_UNUSED __attribute__((unused))
+#else
+#define TB_UNUSED
+#endif
+
+static TB_UNUSED void tb_out_of_memory(void) {
+    fputs("out of memory\n", stderr);
+    exit(1);
+}
+
+static TB_UNUSED void tb_runtime_error(const char *message, int line) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s on line %d\n", message, line);
+    exit(1);
+}
+
+/* Integer arithmetic wraps around on overflow, like the interpreter's, rather than
+   being undefined: it is done in unsigned, which wraps, and converted back. */
+static TB_UNUSED int tb_add_int(int a, int b) {
+    return (int)((unsigned)a + (unsigned)b);
+}
+
+static TB_UNUSED int tb_sub_int(int a, int b) {
+    return (int)((unsigned)a - (unsigned)b);
+}
+
+static TB_UNUSED int tb_mul_int(int a, int b) {
+    return (int)((unsigned)a * (unsigned)b);
+}
+
+static TB_UNUSED int tb_neg_int(int a) {
+    return (int)(0u - (unsigned)a);
+}
+
+static TB_UNUSED int tb_div_int(int a, int b, int line) {
+    if (b == 0) {
+        tb_runtime_error("Integer division by zero", line);
+    }
+    /* INT_MIN / -1 overflows. */
+    if (b == -1) {
+        return tb_neg_int(a);
+    }
+    return a / b;
+}
+
+static TB_UNUSED int tb_return_stack[TB_GOSUB_DEPTH];
+static TB_UNUSED int tb_return_depth = 0;
+static TB_UNUSED int tb_return_to = 0;
+
+static TB_UNUSED void tb_push_return(int line) {
+    if (tb_return_depth == TB_GOSUB_DEPTH) {
+        tb_runtime_error("Too many nested GOSUBs", line);
+    }
+    tb_return_stack[tb_return_depth++] = line;
+}
+
+static TB_UNUSED int tb_pop_return(int line) {
+    if (tb_return_depth == 0) {
+        tb_runtime_error("RETURN without GOSUB", line);
+    }
+    return tb_return_stack[--tb_return_depth];
+}
+
+static TB_UNUSED int tb_call_depth = 0;
+
+static TB_UNUSED void tb_enter_function(int line) {
+    if (tb_call_depth == TB_CALL_DEPTH) {
+        tb_runtime_error("Too many nested function calls", line);
+    }
+    tb_call_depth++;
+}
+
+static TB_UNUSED int tb_column = 0;
+
+static TB_UNUSED void tb_write(const char *s) {
+    fputs(s, stdout);
+    for (; *s != '\0'; s++) {
+        if (*s == '\n') {
+            tb_column = 0;
+        } else if ((*s & 0xC0) != 0x80) {
+            /* Count characters rather than the bytes of UTF-8 sequences. */
+            tb_column++;
+        }
+    }
+}
+
+static TB_UNUSED void tb_print_int(int value) {
+    char buf[16];
+    snprintf(buf, sizeof buf, "%d", value);
+    tb_write(buf);
+}
+
+/* Prints the fewest significant digits that read back as the same double, laid out
+   like JavaScript's Number#toString: 0.000001 through 1e21 in fixed notation, and
+   anything beyond in scientific notation such as 1.5e-7 or 1e+21. */
+static TB_UNUSED void tb_print_float(double value) {
+    if (isnan(value)) {
+        tb_write("nan");
+        return;
+    }
+    if (isinf(value)) {
+        tb_write(value < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (value == 0) {
+        tb_write("0");
+        return;
+    }
+    char sci[32];
+    for (int precision = 0; precision <= 16; precision++) {
+        snprintf(sci, sizeof sci, "%.*e", precision, value);
+        if (strtod(sci, NULL) == value) {
+            break;
+        }
+    }
+    /* sci now looks like "-d.ddde+XX": split it into digits and exponent. */
+    char digits[20];
+    int len = 0;
+    const char *p = sci;
+    for (; *p != 'e'; p++) {
+        if (isdigit((unsigned char)*p)) {
+            digits[len++] = *p;
+        }
+    }
+    while (len > 1 && digits[len - 1] == '0') {
+        len--;
+    }
+    int exponent = atoi(p + 1);
+
+    char buf[64];
+    int out = 0;
+    if (value < 0) {
+        buf[out++] = '-';
+    }
+    if (exponent >= 21 || exponent <= -7) {
+        buf[out++] = digits[0];
+        if (len > 1) {
+            buf[out++] = '.';
+            for (int i = 1; i < len; i++) {
+                buf[out++] = digits[i];
+            }
+        }
+        out += snprintf(buf + out, sizeof buf - out, "e%c%d", exponent < 0 ? '-' : '+',
+                        exponent < 0 ? -exponent : exponent);
+    } else if (exponent < 0) {
+        buf[out++] = '0';
+        buf[out++] = '.';
+        for (int i = -1; i > exponent; i--) {
+            buf[out++] = '0';
+        }
+        for (int i = 0; i < len; i++) {
+            buf[out++] = digits[i];
+        }
+    } else {
+        for (int i = 0; i <= exponent || i < len; i++) {
+            if (i == exponent + 1) {
+                buf[out++] = '.';
+            }
+            buf[out++] = i < len ? digits[i] : '0';
+        }
+    }
+    buf[out] = '\0';
+    tb_write(buf);
+}
+
+static TB_UNUSED void tb_tab(void) {
+    do {
+        tb_write(" ");
+    } while (tb_column % TB_ZONE_WIDTH != 0);
+}
+
+static TB_UNUSED void tb_newline(void) {
+    tb_write("\n");
+}
+
+static TB_UNUSED char *tb_strdup(const char *s) {
+    if (s == NULL) {
+        s = "";
+    }
+    size_t len = strlen(s) + 1;
+    char *copy = malloc(len);
+    if (copy == NULL) {
+        tb_out_of_memory();
+    }
+    memcpy(copy, s, len);
+    return copy;
+}
+
+static TB_UNUSED char *tb_concat(char *a, char *b) {
+    size_t len_a = strlen(a);
+    size_t len_b = strlen(b);
+    char *joined = malloc(len_a + len_b + 1);
+    if (joined == NULL) {
+        tb_out_of_memory();
+    }
+    memcpy(joined, a, len_a);
+    memcpy(joined + len_a, b, len_b + 1);
+    free(a);
+    free(b);
+    return joined;
+}
+
+static TB_UNUSED void tb_assign(char **var, char *value) {
+    free(*var);
+    *var = value;
+}
+
+static TB_UNUSED int tb_str_eq(char *a, char *b) {
+    int equal = strcmp(a, b) == 0;
+    free(a);
+    free(b);
+    return equal;
+}
+
+static TB_UNUSED void tb_print_str(char *s) {
+    tb_write(s);
+    free(s);
+}
+
+/* Skips leading whitespace, then reads the rest of the line. */
+static TB_UNUSED void tb_input_str(char **var) {
+    int c = getchar();
+    while (c != EOF && isspace(c)) {
+        c = getchar();
+    }
+    if (c == EOF) {
+        return;
+    }
+    size_t len = 0;
+    size_t cap = 16;
+    char *line = malloc(cap);
+    if (line == NULL) {
+        tb_out_of_memory();
+    }
+    while (c != EOF && c != '\n') {
+        if (len + 1 == cap) {
+            cap *= 2;
+            line = realloc(line, cap);
+            if (line == NULL) {
+                tb_out_of_memory();
+            }
+        }
+        line[len++] = (char)c;
+        c = getchar();
+    }
+    if (len > 0 && line[len - 1] == '\r') {
+        len--;
+    }
+    line[len] = '\0';
+    tb_assign(var, line);
+}
+"#;
+
 pub struct Emitter {
     source: String,
 }
@@ -11,74 +287,174 @@ pub struct Emitter {
 impl fmt::Display for Emitter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for line in self.source.split_terminator('\n') {
-            write!(f, "{}\n", line)?;
+            writeln!(f, "{}", line)?;
         }
         Ok(())
     }
 }
 
 impl Statement {
-    fn emit(&self) -> String {
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
         let mut statement_str = String::new();
         match self {
-            Statement::Let { ident, expression } => {
-                statement_str.push_str(format!("{} = {};", ident, expression.emit()).as_str());
-            }
-            Statement::Print(msg) => {
-                statement_str.push_str("printf(\"");
-                match msg {
-                    PrintMessage::StrLit(message) => {
-                        statement_str.push_str(message.as_str());
-                        statement_str.push_str("\");");
+            Statement::Let {
+                ident, expression, ..
+            } if types.get(ident) == Some(&Type::Str) => {
+                statement_str.push_str(
+                    format!(
+                        "tb_assign(&{}, {});",
+                        c_ident(ident),
+                        expression.emit_string(types)
+                    )
+                    .as_str(),
+                );
+            }
+            Statement::Let {
+                ident, expression, ..
+            } => {
+                statement_str
+                    .push_str(format!("{} = {};", c_ident(ident), expression.emit(types)).as_str());
+            }
+            Statement::Print { items, newline, .. } => {
+                let mut calls: Vec<String> = items
+                    .iter()
+                    .map(|item| match item {
+                        PrintItem::Tab => "tb_tab();".to_string(),
+                        PrintItem::Expression(expr) => match expression_type(expr, types) {
+                            Type::Int => format!("tb_print_int({});", expr.emit(types)),
+                            Type::Float => format!("tb_print_float({});", expr.emit(types)),
+                            Type::Str => format!("tb_print_str({});", expr.emit_string(types)),
+                        },
+                    })
+                    .collect();
+                if *newline {
+                    calls.push("tb_newline();".to_string());
+                }
+                statement_str.push_str(&calls.join("\n"));
+            }
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => {
+                for (index, branch) in branches.iter().enumerate() {
+                    if index > 0 {
+                        statement_str.push_str(" else ");
                     }
-                    PrintMessage::Expression(expr) => {
-                        statement_str.push_str("%f\\n\",");
-                        statement_str.push_str(expr.emit().as_str());
-                        statement_str.push_str(");");
+                    statement_str.push_str("if (");
+                    statement_str.push_str(branch.condition.emit(types).as_str());
+                    statement_str.push_str(") {\n");
+                    for stat in &branch.statements {
+                        statement_str.push_str(stat.emit(types).as_str());
                     }
-                };
+                    statement_str.push('}');
+                }
+                if let Some(statements) = else_statements {
+                    statement_str.push_str(" else {\n");
+                    for stat in statements {
+                        statement_str.push_str(stat.emit(types).as_str());
+                    }
+                    statement_str.push('}');
+                }
             }
-            Statement::If {
-                comparison,
+            Statement::While {
+                condition,
                 statements,
+                ..
             } => {
-                statement_str.push_str("if (");
-                statement_str.push_str(comparison.emit().as_str());
+                statement_str.push_str("while (");
+                statement_str.push_str(condition.emit(types).as_str());
                 statement_str.push_str(") {\n");
                 for stat in statements {
-                    statement_str.push_str(stat.emit().as_str());
+                    statement_str.push_str(stat.emit(types).as_str());
                 }
-                statement_str.push_str("}");
+                statement_str.push('}');
             }
-            Statement::While {
-                comparison,
+            Statement::For {
+                ident,
+                start,
+                end,
+                step,
                 statements,
+                ..
             } => {
-                statement_str.push_str("while (");
-                statement_str.push_str(comparison.emit().as_str());
-                statement_str.push_str(") {\n");
+                let var = c_ident(ident);
+                let (limit, step_var) = loop_variables(start.span.line);
+                let step_str = match step {
+                    Some(_) => step_var.as_str(),
+                    None => "1",
+                };
+                let int_step = match step {
+                    Some(step) => loop_bound_type(ident, step, types) == Type::Int,
+                    None => types.get(ident) == Some(&Type::Int),
+                };
+                let increment = match int_step {
+                    true => format!("{var} = tb_add_int({var}, {step_str})"),
+                    false => format!("{var} += {step_str}"),
+                };
+                let (step_init, test) = match step {
+                    Some(step) => (
+                        format!(", {step_var} = {}", step.emit(types)),
+                        format!("{step_var} >= 0 ? {var} <= {limit} : {var} >= {limit}"),
+                    ),
+                    None => (String::new(), format!("{var} <= {limit}")),
+                };
+                statement_str.push_str(&format!(
+                    "for ({var} = {}, {limit} = {}{step_init}; {test}; {increment}) {{\n",
+                    start.emit(types),
+                    end.emit(types)
+                ));
                 for stat in statements {
-                    statement_str.push_str(stat.emit().as_str());
+                    statement_str.push_str(stat.emit(types).as_str());
                 }
-                statement_str.push_str("}");
+                statement_str.push('}');
             }
-            Statement::Input { ident } => {
-                statement_str.push_str("if (0 == scanf(\"%f\", &");
-                statement_str.push_str(ident);
+            Statement::Input { ident, .. } if types.get(ident) == Some(&Type::Str) => {
+                statement_str.push_str(format!("tb_input_str(&{});", c_ident(ident)).as_str());
+            }
+            Statement::Input { ident, .. } => {
+                // INPUT variables are always doubles, see `types::infer`.
+                statement_str.push_str("if (0 == scanf(\"%lf\", &");
+                statement_str.push_str(&c_ident(ident));
                 statement_str.push_str(")) {\n");
-                statement_str.push_str(ident);
+                statement_str.push_str(&c_ident(ident));
                 statement_str.push_str(" = 0;\n");
                 statement_str.push_str("scanf(\"%*s\");\n");
                 statement_str.push_str("}\n");
             }
-            Statement::Label { ident } => {
-                statement_str.push_str(ident);
-                statement_str.push(':');
+            Statement::Label { ident, .. } => {
+                // The empty statement keeps C happy when a label ends a block.
+                statement_str.push_str(&label_name(ident));
+                statement_str.push_str(":;");
             }
-            Statement::Goto { ident } => {
+            Statement::Goto { ident, .. } => {
                 statement_str.push_str("goto ");
-                statement_str.push_str(ident);
-                statement_str.push_str(";");
+                statement_str.push_str(&label_name(ident));
+                statement_str.push(';');
+            }
+            Statement::Gosub { ident, span, .. } => {
+                statement_str.push_str(&format!(
+                    "tb_push_return({});\ngoto {};\n{}:;",
+                    span.line,
+                    label_name(ident),
+                    return_label(span.line)
+                ));
+            }
+            Statement::Return {
+                value: Some(value), ..
+            } => {
+                let result = match expression_type(value, types) {
+                    Type::Str => format!("tb_assign(&tb_result, {});", value.emit_string(types)),
+                    _ => format!("tb_result = {};", value.emit(types)),
+                };
+                statement_str.push_str(&result);
+                statement_str.push_str("\ngoto tb_end;");
+            }
+            Statement::Return { span, .. } => {
+                statement_str.push_str(&format!(
+                    "tb_return_to = tb_pop_return({});\ngoto tb_return;",
+                    span.line
+                ));
             }
         };
         statement_str.push('\n');
@@ -86,8 +462,147 @@ impl Statement {
     }
 }
 
+/// C name for a BASIC label. Line numbers aren't valid C identifiers on their own, and
+/// other labels get a prefix so that they can't clash with the `tb_` labels of the
+/// generated code.
+fn label_name(ident: &str) -> String {
+    match is_line_number(ident) {
+        true => format!("line_{ident}"),
+        false => format!("l_{ident}"),
+    }
+}
+
+/// C names of the variables holding the limit and step of the FOR loop on `line`. They
+/// are evaluated once, when the loop starts, and each loop has its own, so that loops on
+/// the same variable which nest through GOSUB or recursion keep theirs apart. They are
+/// declared with the rest of the function's variables rather than in a block around the
+/// loop, since GOSUB and RETURN jump out of and back into blocks, which C doesn't promise
+/// keeps their variables. User variables are all prefixed by `c_ident`, so none can share
+/// these names.
+fn loop_variables(line: u32) -> (String, String) {
+    (format!("tb_to_{line}"), format!("tb_step_{line}"))
+}
+
+/// Type of the variable holding the limit or step `bound` of the FOR loop counting `ident`:
+/// an int only if both are, so that a fractional limit or step isn't truncated.
+fn loop_bound_type(ident: &str, bound: &Expression, types: &HashMap<String, Type>) -> Type {
+    types
+        .get(ident)
+        .unwrap_or(&Type::Float)
+        .join(expression_type(bound, types))
+}
+
+/// Calls `visit` on every statement in `statements`, however deeply nested.
+fn visit_statements(statements: &[Statement], visit: &mut impl FnMut(&Statement)) {
+    for statement in statements {
+        visit(statement);
+        match statement {
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => {
+                for branch in branches {
+                    visit_statements(&branch.statements, visit);
+                }
+                if let Some(statements) = else_statements {
+                    visit_statements(statements, visit);
+                }
+            }
+            Statement::While { statements, .. } | Statement::For { statements, .. } => {
+                visit_statements(statements, visit)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// C name for a BASIC function, kept apart from the variables.
+fn function_name(name: &str) -> String {
+    format!("fn_{}", c_ident(name))
+}
+
+/// C label that the GOSUB on `line` returns to.
+fn return_label(line: u32) -> String {
+    format!("tb_return_{line}")
+}
+
+/// C name for a BASIC variable. `$` can't appear in a C identifier, so string variables
+/// get a prefix of their own. Every variable is prefixed, so that none can clash with a
+/// C keyword, a libc function or a name in the generated code, such as `free` or
+/// `tb_result`.
+fn c_ident(ident: &str) -> String {
+    match ident.strip_suffix('$') {
+        Some(name) => format!("s_{name}"),
+        None => format!("v_{ident}"),
+    }
+}
+
+/// `text` as a C string literal, escaped so that it comes out exactly as written.
+fn c_string_literal(text: &str) -> String {
+    let mut literal = String::from("\"");
+    let mut previous = None;
+    for chr in text.chars() {
+        match chr {
+            '"' => literal.push_str("\\\""),
+            // `??` followed by another character could be read as a trigraph.
+            '?' if previous == Some('?') => literal.push_str("\\?"),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '\r' => literal.push_str("\\r"),
+            // Octal rather than hex, since a hex escape would swallow any digits after it.
+            chr if chr.is_ascii_control() => literal.push_str(&format!("\\{:03o}", chr as u8)),
+            chr => literal.push(chr),
+        }
+        previous = Some(chr);
+    }
+    literal.push('"');
+    literal
+}
+
+impl Condition {
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
+        match self {
+            Condition::Or(left, right) => format!(
+                "{} || {}",
+                left.emit_operand(types),
+                right.emit_operand(types)
+            ),
+            Condition::And(left, right) => format!(
+                "{} && {}",
+                left.emit_operand(types),
+                right.emit_operand(types)
+            ),
+            Condition::Not(condition) => format!("!({})", condition.emit(types)),
+            Condition::Comparison(comparison) => comparison.emit(types),
+        }
+    }
+
+    /// Like `emit`, but with `&&` and `||` parenthesized, so the grouping never depends
+    /// on C's precedence rules.
+    fn emit_operand(&self, types: &HashMap<String, Type>) -> String {
+        match self {
+            Condition::Not(_) | Condition::Comparison(_) => self.emit(types),
+            condition => format!("({})", condition.emit(types)),
+        }
+    }
+}
+
 impl Comparison {
-    fn emit(&self) -> String {
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
+        if expression_type(&self.left, types) == Type::Str {
+            let negate = match self.operator {
+                Token::Equals => "",
+                Token::NotEquals => "!",
+                _ => panic!("Unexpected operator!"),
+            };
+            return format!(
+                "{negate}tb_str_eq({}, {})",
+                self.left.emit_string(types),
+                self.right.emit_string(types)
+            );
+        }
         let op = match self.operator {
             Token::Equals => "==",
             Token::NotEquals => "!=",
@@ -97,66 +612,179 @@ impl Comparison {
             Token::Lte => "<=",
             _ => panic!("Unexpected operator!"),
         };
-        format!("{}{}{}", self.left.emit(), op, self.right.emit())
+        format!("{}{}{}", self.left.emit(types), op, self.right.emit(types))
     }
 }
 
 impl Expression {
-    fn emit(&self) -> String {
-        let mut expr_str = String::from(self.first_term.emit());
-        for term in &self.other_terms {
-            expr_str.push_str(&term.emit())
+    /// Integer addition and subtraction go through `tb_add_int` and `tb_sub_int`, which
+    /// wrap around on overflow like the interpreter.
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
+        let mut expr_str = self.first_term.emit(types, self.span.line);
+        let mut ty = term_type(&self.first_term, types);
+        for comp in &self.other_terms {
+            let other = term_type(&comp.term, types);
+            match comp.operator {
+                Token::Add | Token::Sub if ty == Type::Int && other == Type::Int => {
+                    let helper = match comp.operator {
+                        Token::Add => "tb_add_int",
+                        _ => "tb_sub_int",
+                    };
+                    expr_str = format!(
+                        "{helper}({expr_str}, {})",
+                        comp.term.emit(types, self.span.line)
+                    );
+                }
+                _ => expr_str.push_str(&comp.emit(types, self.span.line)),
+            }
+            ty = ty.join(other);
+        }
+        expr_str
+    }
+
+    /// Emits a string-valued expression, which can only be a concatenation.
+    fn emit_string(&self, types: &HashMap<String, Type>) -> String {
+        let mut expr_str = self.first_term.unary.emit_string(types);
+        for comp in &self.other_terms {
+            expr_str = format!(
+                "tb_concat({expr_str}, {})",
+                comp.term.unary.emit_string(types)
+            );
         }
         expr_str
     }
 }
 
+impl ExprComp {
+    fn emit(&self, types: &HashMap<String, Type>, line: u32) -> String {
+        let op_chr = match self.operator {
+            Token::Add => '+',
+            Token::Sub => '-',
+            _ => panic!("Unexpected operator!"),
+        };
+        format!("{op_chr}{}", self.term.emit(types, line))
+    }
+}
+
 impl Term {
-    fn emit(&self) -> String {
-        let mut term_str = String::from(self.unary.emit());
+    /// Emits the term of an expression on `line`. Integer multiplication goes through
+    /// `tb_mul_int`, which wraps around on overflow, and division through `tb_div_int`,
+    /// which also stops the program on division by zero, both like the interpreter.
+    fn emit(&self, types: &HashMap<String, Type>, line: u32) -> String {
+        let mut term_str = self.unary.emit(types);
+        let mut ty = unary_type(&self.unary, types);
         for comp in &self.components {
-            term_str.push_str(&comp.emit())
+            let other = unary_type(&comp.unary, types);
+            match comp.operator {
+                Token::Mul if ty == Type::Int && other == Type::Int => {
+                    term_str = format!("tb_mul_int({term_str}, {})", comp.unary.emit(types));
+                }
+                Token::Div if ty == Type::Int && other == Type::Int => {
+                    term_str =
+                        format!("tb_div_int({term_str}, {}, {line})", comp.unary.emit(types));
+                }
+                _ => term_str.push_str(&comp.emit(types)),
+            }
+            ty = ty.join(other);
         }
         term_str
     }
 }
 impl TermComp {
-    fn emit(&self) -> String {
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
         let op_chr = match self.operator {
             Token::Mul => '*',
             Token::Div => '/',
             _ => panic!("Unexpected operator!"),
         };
-        format!("{op_chr}{}", self.unary.emit())
+        format!("{op_chr}{}", self.unary.emit(types))
     }
 }
 
 impl Unary {
-    fn emit(&self) -> String {
-        if self.operator.is_none() {
-            self.primary.emit()
-        } else {
-            let op_chr = match self.operator {
-                Some(Token::Add) => '+',
-                Some(Token::Sub) => '-',
-                _ => panic!("Unexpected operator!"),
-            };
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
+        match self {
+            Unary::Negate(unary) if unary_type(unary, types) == Type::Int => {
+                format!("tb_neg_int({})", unary.emit(types))
+            }
+            // Parenthesized so that `a - -b` doesn't come out as `a--b`.
+            Unary::Negate(unary) => format!("(-{})", unary.emit(types)),
+            Unary::Primary(primary) => primary.emit(types),
+        }
+    }
 
-            format!("{}{}", op_chr, self.primary.emit())
+    fn emit_string(&self, types: &HashMap<String, Type>) -> String {
+        match self {
+            Unary::Primary(Primary::Str(text)) => format!("tb_strdup({})", c_string_literal(text)),
+            Unary::Primary(Primary::Ident(id)) => format!("tb_strdup({})", c_ident(id)),
+            Unary::Primary(Primary::Group(expr)) => expr.emit_string(types),
+            // Functions already return a fresh copy.
+            Unary::Primary(call @ Primary::Call { .. }) => call.emit(types),
+            _ => panic!("Unexpected string operand!"),
         }
     }
 }
 
 impl Primary {
-    fn emit(&self) -> String {
+    fn emit(&self, types: &HashMap<String, Type>) -> String {
         match self {
-            Primary::Float(val) => format!("{val}"),
+            Primary::Float(val) => float_literal(*val),
             Primary::Int(val) => format!("{val}"),
-            Primary::Ident(id) => id.clone(),
+            Primary::Ident(id) => c_ident(id),
+            Primary::Str(_) => panic!("Unexpected string operand!"),
+            Primary::Group(expr) => format!("({})", expr.emit(types)),
+            Primary::Call { name, args, .. } => {
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|arg| match is_string_expression(arg) {
+                        true => arg.emit_string(types),
+                        false => arg.emit(types),
+                    })
+                    .collect();
+                format!("{}({})", function_name(name), args.join(", "))
+            }
         }
     }
 }
 
+/// C declaration of `function`, without the trailing `;` or body. Numeric parameters are
+/// always doubles, see `types::infer`.
+fn signature(function: &Function) -> String {
+    let params: Vec<String> = function
+        .params
+        .iter()
+        .map(|param| match function.types.get(param) {
+            Some(Type::Str) => format!("char *{}", c_ident(param)),
+            _ => format!("double {}", c_ident(param)),
+        })
+        .collect();
+    let params = match params.is_empty() {
+        true => "void".to_string(),
+        false => params.join(", "),
+    };
+    let returned = function.types[&return_key(&function.name)].c_name();
+    format!(
+        "static TB_UNUSED {returned}{}{}({params})",
+        if returned.ends_with('*') { "" } else { " " },
+        function_name(&function.name)
+    )
+}
+
+/// C literal for a float, with a decimal point so that C doesn't take `3.0` for an int.
+fn float_literal(val: f64) -> String {
+    let literal = format!("{val:?}");
+    match literal.contains(['.', 'e', 'E']) {
+        true => literal,
+        false => format!("{literal}.0"),
+    }
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Emitter {
     pub fn new() -> Self {
         Emitter {
@@ -166,26 +794,595 @@ impl Emitter {
 
     pub fn build(&mut self, program: Program) {
         self.header();
-        // initialize all variables to NULL. For now everything
-        // is a float
-        for symbol in program.symbols.iter() {
-            self.source.push_str(format!("float {symbol};\n").as_str());
-        }
-        let mut statements = program.statements.iter();
-        while let Some(statement) = statements.next() {
-            let state_str = statement.emit();
+        // Declared up front, so that functions can call each other in any order.
+        for function in &program.functions {
+            self.source.push_str(&signature(function));
+            self.source.push_str(";\n");
+        }
+        for function in &program.functions {
+            self.function(function);
+        }
+        self.source.push_str("int main(void){\n");
+        let symbols = self.declare_variables(
+            &program.symbols,
+            &HashSet::new(),
+            &program.types,
+            &program.statements,
+        );
+        let mut gosub_lines = Vec::new();
+        let mut returns = false;
+        visit_statements(&program.statements, &mut |statement| match statement {
+            Statement::Gosub { span, .. } => gosub_lines.push(span.line),
+            Statement::Return { .. } => returns = true,
+            _ => {}
+        });
+        for statement in program.statements.iter() {
+            let state_str = statement.emit(&program.types);
             self.source.push_str(&state_str);
         }
+        self.free_strings(&symbols, &program.types);
+        self.source.push_str("return 0;\n");
+        // Every RETURN comes here, to jump back to whichever GOSUB it belongs to.
+        if returns {
+            self.source.push_str("tb_return:\n");
+            self.source.push_str("switch (tb_return_to) {\n");
+            for line in gosub_lines {
+                self.source
+                    .push_str(&format!("case {line}: goto {};\n", return_label(line)));
+            }
+            self.source.push_str("}\n");
+        }
         self.footer();
     }
 
+    /// Emits `function` as a C function. Its variables are locals, zeroed on every call
+    /// like the interpreter's, and every RETURN jumps to the end to free its strings.
+    fn function(&mut self, function: &Function) {
+        let types = &function.types;
+        self.source.push_str(&signature(function));
+        self.source.push_str(" {\n");
+        let params: HashSet<String> = function.params.iter().cloned().collect();
+        self.declare_variables(&function.symbols, &params, types, &function.statements);
+        let declaration = match types[&return_key(&function.name)] {
+            Type::Str => "char *tb_result = tb_strdup(\"\");\n".to_string(),
+            ty => format!("{} tb_result = 0;\n", ty.c_name()),
+        };
+        self.source.push_str(&declaration);
+        self.source
+            .push_str(&format!("tb_enter_function({});\n", function.span.line));
+        let mut returns = false;
+        visit_statements(&function.statements, &mut |statement| {
+            returns |= matches!(statement, Statement::Return { .. })
+        });
+        for statement in &function.statements {
+            self.source.push_str(&statement.emit(types));
+        }
+        if returns {
+            self.source.push_str("tb_end:\n");
+        }
+        let mut symbols: Vec<&String> = function.symbols.iter().collect();
+        symbols.sort();
+        self.free_strings(&symbols, types);
+        self.source.push_str("tb_call_depth--;\n");
+        self.source.push_str("return tb_result;\n");
+        self.source.push_str("}\n");
+    }
+
+    /// Declares every one of `symbols` but the `params`, zeroed like the interpreter's,
+    /// along with the temporaries of the FOR loops in `statements`. Returns the symbols in
+    /// the order they were declared.
+    fn declare_variables<'a>(
+        &mut self,
+        symbols: &'a HashSet<String>,
+        params: &HashSet<String>,
+        types: &HashMap<String, Type>,
+        statements: &[Statement],
+    ) -> Vec<&'a String> {
+        let mut symbols: Vec<&String> = symbols.iter().collect();
+        symbols.sort();
+        for symbol in symbols.iter().filter(|symbol| !params.contains(**symbol)) {
+            let declaration = match types.get(*symbol) {
+                Some(Type::Str) => format!("char *{} = NULL;\n", c_ident(symbol)),
+                ty => format!(
+                    "{} {} = 0;\n",
+                    ty.unwrap_or(&Type::Float).c_name(),
+                    c_ident(symbol)
+                ),
+            };
+            self.source.push_str(&declaration);
+        }
+        let mut loop_temporaries = Vec::new();
+        visit_statements(statements, &mut |statement| {
+            if let Statement::For {
+                ident,
+                start,
+                end,
+                step,
+                ..
+            } = statement
+            {
+                let (limit, step_var) = loop_variables(start.span.line);
+                let ty = loop_bound_type(ident, end, types).c_name();
+                loop_temporaries.push(format!("{ty} {limit} = 0;\n"));
+                if let Some(step) = step {
+                    let ty = loop_bound_type(ident, step, types).c_name();
+                    loop_temporaries.push(format!("{ty} {step_var} = 0;\n"));
+                }
+            }
+        });
+        for declaration in loop_temporaries {
+            self.source.push_str(&declaration);
+        }
+        symbols
+    }
+
+    fn free_strings(&mut self, symbols: &[&String], types: &HashMap<String, Type>) {
+        for symbol in symbols {
+            if types.get(*symbol) == Some(&Type::Str) {
+                self.source
+                    .push_str(format!("free({});\n", c_ident(symbol)).as_str());
+            }
+        }
+    }
+
     fn header(&mut self) {
+        self.source.push_str("#include <ctype.h>\n");
+        self.source.push_str("#include <math.h>\n");
         self.source.push_str("#include <stdio.h>\n");
-        self.source.push_str("int main(void){\n");
+        self.source.push_str("#include <stdlib.h>\n");
+        self.source.push_str("#include <string.h>\n");
+        self.source
+            .push_str(&format!("#define TB_ZONE_WIDTH {PRINT_ZONE_WIDTH}\n"));
+        self.source
+            .push_str(&format!("#define TB_GOSUB_DEPTH {GOSUB_DEPTH}\n"));
+        self.source
+            .push_str(&format!("#define TB_CALL_DEPTH {CALL_DEPTH}\n"));
+        self.source.push_str(RUNTIME);
     }
 
     fn footer(&mut self) {
-        self.source.push_str("return 0;\n");
         self.source.push_str("}\n");
     }
 }
+
+#[test]
+fn test_emit_groups() {
+    let source = "LET a = 1\nLET b = (a + 2) * ((3 - a))\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "v_b = tb_mul_int((tb_add_int(v_a, 2)), ((tb_sub_int(3, v_a))));\n"
+    );
+}
+
+#[test]
+fn test_emit_negation() {
+    let source = "LET a = 1\nLET b = a - -a * -(2 - a)\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "v_b = tb_sub_int(v_a, tb_mul_int(tb_neg_int(v_a), tb_neg_int((tb_sub_int(2, v_a)))));\n"
+    );
+}
+
+#[test]
+fn test_emit_conditions() {
+    let source = "LET a = 1\nIF NOT a > 1 AND a < 3 OR (a == 2 OR a != 4) AND a >= 0 THEN\nENDIF\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "if ((!(v_a>1) && v_a<3) || ((v_a==2 || v_a!=4) && v_a>=0)) {\n}\n"
+    );
+}
+
+#[test]
+fn test_emit_else_branches() {
+    let source = "LET a = 1\nIF a > 1 THEN\nELSEIF a > 0 THEN\nPRINT a\nELSE\nLET a = 2\nENDIF\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "if (v_a>1) {\n} else if (v_a>0) {\ntb_print_int(v_a);\ntb_newline();\n} else {\nv_a = 2;\n}\n"
+    );
+}
+
+#[test]
+fn test_emit_for_loops() {
+    let source = "LET n = 3\nFOR i = 1 TO n\nFOR x = n TO 0 STEP -0.5\nNEXT x\nNEXT i\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "for (v_i = 1, tb_to_2 = v_n; v_i <= tb_to_2; v_i = tb_add_int(v_i, 1)) {\n\
+         for (v_x = v_n, tb_to_3 = 0, tb_step_3 = (-0.5); \
+         tb_step_3 >= 0 ? v_x <= tb_to_3 : v_x >= tb_to_3; v_x += tb_step_3) {\n}\n}\n"
+    );
+    let mut emitter = Emitter::new();
+    emitter.build(program);
+    assert!(emitter
+        .to_string()
+        .contains("int tb_to_2 = 0;\ndouble tb_to_3 = 0;\ndouble tb_step_3 = 0;\n"));
+}
+
+#[test]
+fn test_emit_gosub() {
+    let source = "GOSUB twice\nGOSUB twice\nGOTO end\nLABEL twice\nRETURN\nLABEL end\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "tb_push_return(2);\ngoto l_twice;\ntb_return_2:;\n"
+    );
+    assert_eq!(
+        program.statements[4].emit(&program.types),
+        "tb_return_to = tb_pop_return(5);\ngoto tb_return;\n"
+    );
+    let mut emitter = Emitter::new();
+    emitter.build(program);
+    assert!(emitter.to_string().ends_with(
+        "return 0;\ntb_return:\nswitch (tb_return_to) {\n\
+         case 1: goto tb_return_1;\ncase 2: goto tb_return_2;\n}\n}\n"
+    ));
+}
+
+#[test]
+fn test_emit_line_numbers() {
+    let source = "10 PRINT \"hi\"\n20 GOTO 10\n";
+    let program = build_dialect(source, crate::parser::Dialect::LineNumbers).unwrap();
+    let emitted: Vec<String> = program
+        .statements
+        .iter()
+        .map(|stat| stat.emit(&program.types))
+        .collect();
+    assert_eq!(
+        emitted,
+        vec![
+            "line_10:;\n",
+            "tb_print_str(tb_strdup(\"hi\"));\ntb_newline();\n",
+            "line_20:;\n",
+            "goto line_10;\n"
+        ]
+    );
+}
+
+#[test]
+fn test_emit_types() {
+    let source = "LET i = 7\nLET f = 3.0\nPRINT i / 2\nPRINT f / 2\nINPUT n\nPRINT i * 3 / i / f\n";
+    let program = build_source(source).unwrap();
+    let mut emitter = Emitter::new();
+    emitter.build(program);
+    let c_source = emitter.to_string();
+    assert!(
+        c_source.contains("double v_f = 0;\nint v_i = 0;\ndouble v_n = 0;\n"),
+        "{c_source}"
+    );
+    assert!(c_source.contains("v_f = 3.0;\n"));
+    assert!(c_source.contains("tb_print_int(tb_div_int(v_i, 2, 3));\ntb_newline();\n"));
+    assert!(c_source.contains("tb_print_float(v_f/2);\ntb_newline();\n"));
+    assert!(c_source.contains("scanf(\"%lf\", &v_n)"));
+    assert!(c_source.contains("tb_print_float(tb_div_int(tb_mul_int(v_i, 3), v_i, 6)/v_f);\n"));
+}
+
+#[test]
+fn test_emit_strings() {
+    let source = "\
+LET name$ = \"bob\"
+INPUT name$
+LET greeting$ = \"hi \" + (name$ + \"!\")
+IF greeting$ != \"say \\\"hi\\\"\" THEN
+PRINT greeting$
+ENDIF
+";
+    let program = build_source(source).unwrap();
+    let emitted: Vec<String> = program
+        .statements
+        .iter()
+        .map(|stat| stat.emit(&program.types))
+        .collect();
+    assert_eq!(
+        emitted,
+        vec![
+            "tb_assign(&s_name, tb_strdup(\"bob\"));\n",
+            "tb_input_str(&s_name);\n",
+            "tb_assign(&s_greeting, tb_concat(tb_strdup(\"hi \"), \
+             tb_concat(tb_strdup(s_name), tb_strdup(\"!\"))));\n",
+            "if (!tb_str_eq(tb_strdup(s_greeting), tb_strdup(\"say \\\"hi\\\"\"))) {\n\
+             tb_print_str(tb_strdup(s_greeting));\ntb_newline();\n}\n",
+        ]
+    );
+}
+
+#[test]
+fn test_emit_print_items() {
+    let source = "LET a = 1\nPRINT \"a\", a; a;\nPRINT\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[1].emit(&program.types),
+        "tb_print_str(tb_strdup(\"a\"));\ntb_tab();\ntb_print_int(v_a);\ntb_print_int(v_a);\n"
+    );
+    assert_eq!(
+        program.statements[2].emit(&program.types),
+        "tb_newline();\n"
+    );
+}
+
+#[test]
+fn test_c_string_literal() {
+    assert_eq!(
+        c_string_literal("100% \"done\"\n\tC:\\ é??!\u{7}"),
+        "\"100% \\\"done\\\"\\n\\tC:\\\\ é?\\?!\\007\""
+    );
+}
+
+#[test]
+fn test_emit_functions() {
+    let source = "\
+FUNCTION twice$(s$, n)
+    IF n > 0 THEN
+        RETURN s$ + s$
+    ENDIF
+ENDFUNCTION
+FUNCTION one()
+    RETURN 1
+ENDFUNCTION
+LET a$ = twice$(\"ab\" + \"c\", one())
+";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        program.statements[0].emit(&program.types),
+        "tb_assign(&s_a, fn_s_twice(tb_concat(tb_strdup(\"ab\"), tb_strdup(\"c\")), fn_v_one()));\n"
+    );
+    let mut emitter = Emitter::new();
+    emitter.build(program);
+    let c_source = emitter.to_string();
+    assert!(
+        c_source.contains(
+            "static TB_UNUSED char *fn_s_twice(char *s_s, double v_n);\n\
+             static TB_UNUSED int fn_v_one(void);\n"
+        ),
+        "{c_source}"
+    );
+    assert!(c_source.contains(
+        "static TB_UNUSED char *fn_s_twice(char *s_s, double v_n) {\n\
+         char *tb_result = tb_strdup(\"\");\n\
+         tb_enter_function(1);\n\
+         if (v_n>0) {\n\
+         tb_assign(&tb_result, tb_concat(tb_strdup(s_s), tb_strdup(s_s)));\n\
+         goto tb_end;\n\
+         }\n\
+         tb_end:\n\
+         free(s_s);\n\
+         tb_call_depth--;\n\
+         return tb_result;\n\
+         }\n"
+    ));
+    assert!(c_source.contains("static TB_UNUSED int fn_v_one(void) {\nint tb_result = 0;\n"));
+}
+
+/// Builds `source` with the C compiler and runs it with `input` on stdin, returning what
+/// it printed on stdout and on stderr, or `None` if there is no C compiler to build it with.
+#[cfg(test)]
+fn run_compiled(name: &str, source: &str, input: &str) -> Option<(String, String)> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let Ok(toolchain) = crate::toolchain::Toolchain::new(None, None, vec![]) else {
+        eprintln!("skipping the compiled half of {name}: no C compiler found");
+        return None;
+    };
+    let program = build_source(source).unwrap();
+    let mut emitter = Emitter::new();
+    emitter.build(program);
+
+    let dir = std::env::temp_dir().join(format!("tnybsc-test-{}-{name}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let c_path = dir.join(format!("{name}.c"));
+    let build_path = dir.join(format!("{name}.out"));
+    std::fs::write(&c_path, emitter.to_string()).unwrap();
+    toolchain.compile(&c_path, &build_path).unwrap();
+
+    let mut child = Command::new(&build_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+    Some((
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    ))
+}
+
+/// Runs `source` with the interpreter and, if there is a C compiler, as a compiled program,
+/// and checks that both print the same and stop with the same runtime error, if any.
+/// Returns what the interpreter printed.
+#[cfg(test)]
+fn assert_backends_agree(name: &str, source: &str, input: &str) -> String {
+    let program = build_source(source).unwrap();
+    let mut stdout = Vec::new();
+    let result = crate::compiler::interpreter::Interpreter::new().run(
+        &program,
+        &mut input.as_bytes(),
+        &mut stdout,
+    );
+    let stdout = String::from_utf8(stdout).unwrap();
+    // As the compiled program reports it, see `tb_runtime_error`.
+    let stderr = match result {
+        Ok(()) => String::new(),
+        Err(err) => format!(
+            "error: {} on line {}\n",
+            err.message(),
+            err.span().unwrap().line
+        ),
+    };
+    if let Some(compiled) = run_compiled(name, source, input) {
+        assert_eq!(compiled, (stdout.clone(), stderr), "{name}");
+    }
+    stdout
+}
+
+#[test]
+fn test_compile_clashing_names() {
+    // Each of these names is taken in C, by the string variable `x$`, by libc or by C
+    // itself.
+    let source = "\
+LET str_x = 1
+LET s_x = 2
+LET x$ = \"a\"
+LET free = 3
+LET int = 4
+LABEL tb_return
+PRINT str_x, s_x, x$, free, int
+";
+    assert_backends_agree("clashing_names", source, "");
+}
+
+#[test]
+fn test_compile_loop_temporaries() {
+    let source = "\
+LET tb_to_i = 5
+LET tb_step_i = 0.5
+FOR i = 1 TO 2 STEP 1
+PRINT i, tb_to_i, tb_step_i
+NEXT i
+";
+    assert_backends_agree("loop_temporaries", source, "");
+}
+
+#[test]
+fn test_compile_function_names() {
+    // `tb_result` and `fn_g` are the C names of a function's result and of `g`.
+    let source = "\
+FUNCTION f(tb_result, tb_end)
+    RETURN tb_result + tb_end
+ENDFUNCTION
+FUNCTION g()
+    RETURN 1
+ENDFUNCTION
+LET fn_g = 1
+PRINT fn_g, g(), f(2, 3)
+";
+    assert_eq!(
+        assert_backends_agree("function_names", source, ""),
+        "1             1             5\n"
+    );
+}
+
+#[test]
+fn test_compile_uppercase_strings() {
+    let source = "\
+INPUT NAME$
+LET A$ = \"Hi, \" + NAME$
+LET Count2 = 2
+PRINT A$, Count2
+";
+    assert_eq!(
+        assert_backends_agree("uppercase_strings", source, "Ada\n"),
+        "Hi, Ada       2\n"
+    );
+}
+
+#[test]
+fn test_compile_division_by_zero() {
+    let source = "\
+LET a = 0
+PRINT 1.0 / a
+PRINT 1 / a
+PRINT \"unreachable\"
+";
+    assert_eq!(
+        assert_backends_agree("division_by_zero", source, ""),
+        "inf\n"
+    );
+}
+
+#[test]
+fn test_compile_numeric_input() {
+    // `scanf("%lf")` reads the number at the front of a word and leaves the rest, which
+    // the next INPUT then fails to read and skips.
+    let source = "INPUT a\nINPUT b\nINPUT c\nINPUT d\nINPUT e\nPRINT a; \" \"; b; \" \"; c; \" \"; d; \" \"; e\n";
+    assert_eq!(
+        assert_backends_agree("numeric_input", source, "12abc 3.5e2x -.5\n"),
+        "12 0 350 0 -0.5\n"
+    );
+    assert_eq!(
+        assert_backends_agree("hex_input", source, "0x1.8p1 1e+x 0xg 5\n"),
+        "3 1 0 0 5\n"
+    );
+}
+
+#[test]
+fn test_compile_integer_overflow() {
+    let source = "\
+LET max = 2147483647
+LET min = -max - 1
+PRINT max + 1; \" \"; min - 1; \" \"; max * 2; \" \"; -min; \" \"; min / -1
+FOR i = max - 1 TO max
+IF i < 0 THEN
+GOTO done
+ENDIF
+NEXT i
+LABEL done
+PRINT i
+";
+    assert_eq!(
+        assert_backends_agree("integer_overflow", source, ""),
+        "-2147483648 2147483647 -2 -2147483648 -2147483648\n-2147483648\n"
+    );
+}
+
+#[test]
+fn test_compile_loops_reentered_through_gosub() {
+    // Both loops count with `i`, but each keeps its own limit and step.
+    let source = "\
+FOR i = 1 TO 3
+GOSUB inner
+PRINT \"outer\"; i
+NEXT i
+GOTO done
+LABEL inner
+LET saved = i
+FOR i = 10 TO 0 STEP -5
+NEXT i
+LET i = saved
+RETURN
+LABEL done
+";
+    assert_eq!(
+        assert_backends_agree("loops_through_gosub", source, ""),
+        "outer1\nouter2\nouter3\n"
+    );
+}
+
+#[test]
+fn test_compile_fractional_loop_bounds() {
+    // An int variable counting to a fractional limit stops short of it, rather than at
+    // the limit truncated toward zero.
+    let source = "\
+FOR i = 3 TO 1.5 STEP -1
+PRINT i;
+NEXT i
+PRINT
+FOR i = -3 TO -1.5
+PRINT i;
+NEXT i
+PRINT
+FOR i = 2147483646 TO 3000000000.0
+PRINT i;
+IF i < 0 THEN
+GOTO done
+ENDIF
+NEXT i
+LABEL done
+PRINT
+";
+    assert_eq!(
+        assert_backends_agree("fractional_loop_bounds", source, ""),
+        "32\n-3-2\n21474836462147483647-2147483648\n"
+    );
+}
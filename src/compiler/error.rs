@@ -0,0 +1,162 @@
+use crate::compiler::diagnostic::{Diagnostic, Span};
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong between reading the source and running the result.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompileError {
+    /// The source text could not be split into tokens.
+    Lexical { message: String, span: Span },
+    /// The tokens do not form a valid statement or expression.
+    Syntax {
+        message: String,
+        span: Span,
+        help: Option<String>,
+    },
+    /// The program is well formed but meaningless, e.g. it uses a variable that is never set.
+    Semantic { message: String, span: Span },
+    /// The interpreter stopped the program part way through, e.g. on a RETURN without a
+    /// GOSUB or an integer division by zero.
+    Runtime { message: String, span: Span },
+    /// Writing, compiling or running the generated code failed.
+    Backend { message: String },
+}
+
+impl CompileError {
+    pub fn lexical(message: impl Into<String>, span: Span) -> CompileError {
+        CompileError::Lexical {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn syntax(message: impl Into<String>, span: Span) -> CompileError {
+        CompileError::Syntax {
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn semantic(message: impl Into<String>, span: Span) -> CompileError {
+        CompileError::Semantic {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn runtime(message: impl Into<String>, span: Span) -> CompileError {
+        CompileError::Runtime {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn backend(message: impl Into<String>) -> CompileError {
+        CompileError::Backend {
+            message: message.into(),
+        }
+    }
+
+    /// Attaches a suggestion for fixing a syntax error.
+    pub fn with_help(self, help: impl Into<String>) -> CompileError {
+        match self {
+            CompileError::Syntax { message, span, .. } => CompileError::Syntax {
+                message,
+                span,
+                help: Some(help.into()),
+            },
+            other => other,
+        }
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        match self {
+            CompileError::Syntax { help, .. } => help.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CompileError::Lexical { message, .. }
+            | CompileError::Syntax { message, .. }
+            | CompileError::Semantic { message, .. }
+            | CompileError::Runtime { message, .. }
+            | CompileError::Backend { message } => message,
+        }
+    }
+
+    /// Where in the source the error was found, if it came from the source at all.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::Lexical { span, .. }
+            | CompileError::Syntax { span, .. }
+            | CompileError::Semantic { span, .. }
+            | CompileError::Runtime { span, .. } => Some(*span),
+            CompileError::Backend { .. } => None,
+        }
+    }
+
+    /// Formats the error for the terminal, with an excerpt of `source` when it has a span.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        match self.span() {
+            Some(span) => {
+                let mut diagnostic = Diagnostic::new(self.message(), span);
+                diagnostic.help = self.help().map(str::to_string);
+                diagnostic.render(file_name, source)
+            }
+            None => format!("error: {}\n", self.message()),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            CompileError::Lexical { .. } => "lexical error",
+            CompileError::Syntax { .. } => "syntax error",
+            CompileError::Semantic { .. } => "semantic error",
+            CompileError::Runtime { .. } => "runtime error",
+            CompileError::Backend { .. } => "backend error",
+        };
+        match self.span() {
+            Some(span) => write!(
+                f,
+                "{kind} at {}:{}: {}",
+                span.line,
+                span.col,
+                self.message()
+            ),
+            None => write!(f, "{kind}: {}", self.message()),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<std::io::Error> for CompileError {
+    fn from(err: std::io::Error) -> CompileError {
+        CompileError::backend(format!("I/O error: {err}"))
+    }
+}
+
+#[test]
+fn test_display() {
+    let err = CompileError::semantic("Undeclared identifier 'x'", Span::new(8, 9, 3, 9));
+    assert_eq!(
+        err.to_string(),
+        "semantic error at 3:9: Undeclared identifier 'x'"
+    );
+    let err = CompileError::runtime("RETURN without GOSUB", Span::new(20, 26, 4, 1));
+    assert_eq!(
+        err.to_string(),
+        "runtime error at 4:1: RETURN without GOSUB"
+    );
+    let err = CompileError::backend("gcc exited with status 1");
+    assert_eq!(err.to_string(), "backend error: gcc exited with status 1");
+    assert_eq!(
+        err.render("fib.tbsc", ""),
+        "error: gcc exited with status 1\n"
+    );
+}
@@ -0,0 +1,1211 @@
+use crate::compiler::diagnostic::Span;
+use crate::compiler::error::CompileError;
+#[cfg(test)]
+use crate::compiler::testing::{build_dialect, build_source};
+use crate::compiler::types::{return_key, Type};
+use crate::lexer::Token;
+use crate::parser::{
+    Comparison, Condition, ExprComp, Expression, Function, Primary, PrintItem, Program, Statement,
+    Term, TermComp, Unary, CALL_DEPTH, GOSUB_DEPTH, PRINT_ZONE_WIDTH,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// A statement lowered to straight-line code, so that a GOTO can land anywhere,
+/// including inside or outside of IF and WHILE bodies.
+enum Op<'a> {
+    Let(&'a str, &'a Expression),
+    /// The items to print, and whether to end with a newline.
+    Print(&'a [PrintItem], bool),
+    Input(&'a str),
+    Jump(usize),
+    /// Jump to the given index when the condition is false.
+    JumpUnless(&'a Condition, usize),
+    /// Sets a FOR loop's variable to its start value, and records its limit and step
+    /// under the index of this op.
+    ForStart(
+        &'a str,
+        &'a Expression,
+        &'a Expression,
+        Option<&'a Expression>,
+    ),
+    /// Jump to the given index once the FOR loop's variable is past its limit. The first
+    /// index is the loop's `ForStart`.
+    ForTest(&'a str, usize, usize),
+    /// Adds the step to the FOR loop's variable. The index is the loop's `ForStart`.
+    ForNext(&'a str, usize),
+    /// Placeholder for a GOTO until every label's position is known.
+    Goto(&'a str),
+    /// Jump to the given index, remembering where to come back to. The span is the GOSUB's.
+    Call(usize, Span),
+    /// Placeholder for a GOSUB until every label's position is known.
+    Gosub(&'a str, Span),
+    Return(Span),
+    /// Leaves the function being run with the value of the expression.
+    ReturnValue(&'a Expression),
+}
+
+/// A runtime value, following C's rules: arithmetic on two ints stays an int, and anything
+/// involving a float is a double. Int arithmetic wraps around on overflow, as it does in
+/// the C backend's `tb_add_int` and friends.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i32),
+    Float(f64),
+    Str(String),
+}
+
+impl Value {
+    fn zero(ty: Type) -> Value {
+        match ty {
+            Type::Int => Value::Int(0),
+            Type::Float => Value::Float(0.0),
+            Type::Str => Value::Str(String::new()),
+        }
+    }
+
+    /// Converts to `ty` the way C's assignment does, truncating floats stored in ints.
+    fn convert(self, ty: Type) -> Value {
+        match (self, ty) {
+            (Value::Float(val), Type::Int) => Value::Int(val as i32),
+            (Value::Int(val), Type::Float) => Value::Float(val as f64),
+            (value, _) => value,
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(val) => *val as f64,
+            Value::Float(val) => *val,
+            Value::Str(_) => panic!("String used as a number!"),
+        }
+    }
+
+    fn arithmetic(self, operator: &Token, other: Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Str(mut left), Value::Str(right)) if *operator == Token::Add => {
+                left.push_str(&right);
+                Ok(Value::Str(left))
+            }
+            (Value::Str(_), _) | (_, Value::Str(_)) => {
+                Err(format!("Operator {operator} cannot be used on strings"))
+            }
+            (Value::Int(left), Value::Int(right)) => match operator {
+                Token::Add => Ok(Value::Int(left.wrapping_add(right))),
+                Token::Sub => Ok(Value::Int(left.wrapping_sub(right))),
+                Token::Mul => Ok(Value::Int(left.wrapping_mul(right))),
+                Token::Div if right == 0 => Err("Integer division by zero".to_string()),
+                Token::Div => Ok(Value::Int(left.wrapping_div(right))),
+                _ => panic!("Unexpected operator!"),
+            },
+            (left, right) => {
+                let (left, right) = (left.as_float(), right.as_float());
+                Ok(Value::Float(match operator {
+                    Token::Add => left + right,
+                    Token::Sub => left - right,
+                    Token::Mul => left * right,
+                    Token::Div => left / right,
+                    _ => panic!("Unexpected operator!"),
+                }))
+            }
+        }
+    }
+}
+
+/// Runs programs directly, without going through C. Variables live as long as the
+/// interpreter, so that consecutive programs can share them.
+pub struct Interpreter {
+    /// Variables of the main program, or of the function call in progress.
+    variables: HashMap<String, Value>,
+    /// Limit and step of each FOR loop in the main program or the function call in
+    /// progress, by the index of its `ForStart`, like the C backend's `tb_to_` and
+    /// `tb_step_` variables.
+    for_loops: HashMap<usize, (Value, Option<Value>)>,
+    /// Column the next character printed will land in, for `,` to tab from.
+    column: usize,
+}
+
+/// What running a program needs besides the variables.
+struct Context<'p, 'io> {
+    /// Every function of the program, with its body lowered.
+    functions: &'p HashMap<&'p str, (&'p Function, Vec<Op<'p>>)>,
+    /// Types of the variables in scope.
+    types: &'p HashMap<String, Type>,
+    input: &'io mut dyn BufRead,
+    output: &'io mut dyn Write,
+    /// How many function calls are in progress.
+    depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            variables: HashMap::new(),
+            for_loops: HashMap::new(),
+            column: 0,
+        }
+    }
+
+    /// Resets every variable to zero, keeping its type.
+    pub fn clear(&mut self) {
+        for value in self.variables.values_mut() {
+            *value = match value {
+                Value::Int(_) => Value::Int(0),
+                Value::Float(_) => Value::Float(0.0),
+                Value::Str(_) => Value::Str(String::new()),
+            };
+        }
+        self.for_loops.clear();
+    }
+
+    /// Runs `program`, reading `INPUT` from `input` and writing `PRINT` output to `output`
+    /// exactly as the compiled C program would.
+    pub fn run(
+        &mut self,
+        program: &Program,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<(), CompileError> {
+        let ops = lower(&program.statements);
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| {
+                (
+                    function.name.as_str(),
+                    (function, lower(&function.statements)),
+                )
+            })
+            .collect();
+        self.column = 0;
+        for ident in &program.symbols {
+            self.variables
+                .entry(ident.clone())
+                .or_insert(Value::zero(program.types[ident]));
+        }
+        let mut context = Context {
+            functions: &functions,
+            types: &program.types,
+            input,
+            output,
+            depth: 0,
+        };
+        self.execute(&mut context, &ops)?;
+        context.output.flush()?;
+        Ok(())
+    }
+
+    /// Runs `ops` until they run out, or until a RETURN hands back the value of the
+    /// function they are the body of.
+    fn execute(
+        &mut self,
+        context: &mut Context,
+        ops: &[Op],
+    ) -> Result<Option<Value>, CompileError> {
+        let mut pc = 0;
+        // Where each GOSUB still waiting for its RETURN will carry on from.
+        let mut returns = Vec::new();
+        while let Some(op) = ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Let(ident, expression) => {
+                    let value = self.eval(context, expression)?;
+                    self.assign(context.types, ident, value);
+                }
+                Op::Print(items, newline) => {
+                    for item in items.iter() {
+                        match item {
+                            PrintItem::Tab => {
+                                let spaces = PRINT_ZONE_WIDTH - self.column % PRINT_ZONE_WIDTH;
+                                self.write(context.output, &" ".repeat(spaces))?;
+                            }
+                            PrintItem::Expression(expression) => {
+                                let text = match self.eval(context, expression)? {
+                                    Value::Int(val) => val.to_string(),
+                                    Value::Float(val) => format_float(val),
+                                    Value::Str(text) => text,
+                                };
+                                self.write(context.output, &text)?;
+                            }
+                        }
+                    }
+                    if *newline {
+                        self.write(context.output, "\n")?;
+                    }
+                }
+                Op::Input(ident) if context.types.get(*ident) == Some(&Type::Str) => {
+                    context.output.flush()?;
+                    if let Some(line) = read_line(context.input)? {
+                        self.assign(context.types, ident, Value::Str(line));
+                    }
+                }
+                Op::Input(ident) => {
+                    context.output.flush()?;
+                    // The variable is left alone once input runs out.
+                    if let Some(value) = read_number(context.input)? {
+                        self.assign(context.types, ident, Value::Float(value));
+                    }
+                }
+                Op::Jump(target) => pc = *target,
+                Op::JumpUnless(condition, target) => {
+                    if !self.test(context, condition)? {
+                        pc = *target;
+                    }
+                }
+                Op::ForStart(ident, start, end, step) => {
+                    // Same order as the C backend, in case `end` or `step` use the variable.
+                    // Like the C backend's `tb_to_` and `tb_step_`, the limit and step are only
+                    // made ints when both they and the variable are, so that a fractional
+                    // limit isn't truncated.
+                    let ty = context.types[*ident];
+                    let bound = |value: Value| match ty {
+                        Type::Int => value,
+                        ty => value.convert(ty),
+                    };
+                    let value = self.eval(context, start)?;
+                    self.assign(context.types, ident, value);
+                    let limit = bound(self.eval(context, end)?);
+                    let step = match step {
+                        Some(step) => Some(bound(self.eval(context, step)?)),
+                        None => None,
+                    };
+                    self.for_loops.insert(pc - 1, (limit, step));
+                }
+                Op::ForTest(ident, start, target) => {
+                    let value = self.variables[*ident].as_float();
+                    let (limit, step) = match self.for_loops.get(start) {
+                        Some((limit, step)) => {
+                            (limit.as_float(), step.as_ref().map(Value::as_float))
+                        }
+                        None => (0.0, None),
+                    };
+                    // Written like the C backend's test, so that NaNs behave the same.
+                    let more = match step {
+                        Some(step) if step >= 0.0 => value <= limit,
+                        Some(_) => value >= limit,
+                        None => value <= limit,
+                    };
+                    if !more {
+                        pc = *target;
+                    }
+                }
+                Op::ForNext(ident, start) => {
+                    let step = match self.for_loops.get(start) {
+                        Some((_, Some(step))) => step.clone(),
+                        _ => Value::Int(1),
+                    };
+                    let value = match (&self.variables[*ident], step) {
+                        (Value::Int(value), Value::Int(step)) => {
+                            Value::Int(value.wrapping_add(step))
+                        }
+                        (value, step) => Value::Float(value.as_float() + step.as_float()),
+                    };
+                    self.assign(context.types, ident, value);
+                }
+                Op::Call(target, span) => {
+                    if returns.len() == GOSUB_DEPTH {
+                        return Err(CompileError::runtime("Too many nested GOSUBs", *span));
+                    }
+                    returns.push(pc);
+                    pc = *target;
+                }
+                Op::Return(span) => {
+                    pc = returns
+                        .pop()
+                        .ok_or_else(|| CompileError::runtime("RETURN without GOSUB", *span))?;
+                }
+                Op::ReturnValue(expression) => return Ok(Some(self.eval(context, expression)?)),
+                Op::Goto(ident) => unreachable!("GOTO {ident} was not resolved"),
+                Op::Gosub(ident, _) => unreachable!("GOSUB {ident} was not resolved"),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Calls the function `name` with its own, freshly zeroed variables, and returns what
+    /// it RETURNs, converted to its return type.
+    fn call(
+        &mut self,
+        context: &mut Context,
+        name: &str,
+        args: &[Expression],
+    ) -> Result<Value, CompileError> {
+        let functions = context.functions;
+        let (function, ops) = &functions[name];
+        if context.depth == CALL_DEPTH {
+            return Err(CompileError::runtime(
+                "Too many nested function calls",
+                function.span,
+            ));
+        }
+        let mut frame: HashMap<String, Value> = function
+            .symbols
+            .iter()
+            .map(|ident| (ident.clone(), Value::zero(function.types[ident])))
+            .collect();
+        for (param, arg) in function.params.iter().zip(args) {
+            let value = self.eval(context, arg)?;
+            frame.insert(param.clone(), value.convert(function.types[param]));
+        }
+
+        let variables = std::mem::replace(&mut self.variables, frame);
+        let for_loops = std::mem::take(&mut self.for_loops);
+        let types = std::mem::replace(&mut context.types, &function.types);
+        context.depth += 1;
+        let result = self.execute(context, ops);
+        context.depth -= 1;
+        context.types = types;
+        self.variables = variables;
+        self.for_loops = for_loops;
+
+        let returned = function.types[&return_key(name)];
+        Ok(result?.unwrap_or(Value::zero(returned)).convert(returned))
+    }
+
+    /// Prints `text`, keeping track of the column like the C backend's `tb_write`.
+    fn write(&mut self, output: &mut dyn Write, text: &str) -> std::io::Result<()> {
+        match text.rfind('\n') {
+            Some(pos) => self.column = text[pos + 1..].chars().count(),
+            None => self.column += text.chars().count(),
+        }
+        write!(output, "{text}")
+    }
+
+    /// Stores `value` in `ident`, converted to the variable's type.
+    fn assign(&mut self, types: &HashMap<String, Type>, ident: &str, value: Value) {
+        let value = match types.get(ident) {
+            Some(ty) => value.convert(*ty),
+            None => value,
+        };
+        self.variables.insert(ident.to_string(), value);
+    }
+
+    /// Evaluates `condition`, short-circuiting `AND` and `OR` like C's `&&` and `||`.
+    fn test(&mut self, context: &mut Context, condition: &Condition) -> Result<bool, CompileError> {
+        Ok(match condition {
+            Condition::Or(left, right) => self.test(context, left)? || self.test(context, right)?,
+            Condition::And(left, right) => {
+                self.test(context, left)? && self.test(context, right)?
+            }
+            Condition::Not(condition) => !self.test(context, condition)?,
+            Condition::Comparison(comparison) => self.compare(context, comparison)?,
+        })
+    }
+
+    fn compare(
+        &mut self,
+        context: &mut Context,
+        comparison: &Comparison,
+    ) -> Result<bool, CompileError> {
+        let left = self.eval(context, &comparison.left)?;
+        let right = self.eval(context, &comparison.right)?;
+        let error = runtime_error(&comparison.left);
+        let ordering = match (&left, &right) {
+            (Value::Int(left), Value::Int(right)) => left.partial_cmp(right),
+            (Value::Str(left), Value::Str(right)) => match comparison.operator {
+                Token::Equals | Token::NotEquals => left.partial_cmp(right),
+                _ => {
+                    return Err(error(
+                        "Strings can only be compared with '==' or '!='".into(),
+                    ))
+                }
+            },
+            (Value::Str(_), _) | (_, Value::Str(_)) => {
+                return Err(error("Cannot compare a string with a number".to_string()))
+            }
+            (left, right) => left.as_float().partial_cmp(&right.as_float()),
+        };
+        // Like C, every comparison with a NaN is false, except `!=`.
+        Ok(match (&comparison.operator, ordering) {
+            (Token::NotEquals, None) => true,
+            (_, None) => false,
+            (Token::Equals, Some(ordering)) => ordering.is_eq(),
+            (Token::NotEquals, Some(ordering)) => ordering.is_ne(),
+            (Token::Gt, Some(ordering)) => ordering.is_gt(),
+            (Token::Gte, Some(ordering)) => ordering.is_ge(),
+            (Token::Lt, Some(ordering)) => ordering.is_lt(),
+            (Token::Lte, Some(ordering)) => ordering.is_le(),
+            _ => panic!("Unexpected operator!"),
+        })
+    }
+
+    fn eval(
+        &mut self,
+        context: &mut Context,
+        expression: &Expression,
+    ) -> Result<Value, CompileError> {
+        let mut value = self.eval_term(context, &expression.first_term, expression)?;
+        for ExprComp { operator, term } in &expression.other_terms {
+            let other = self.eval_term(context, term, expression)?;
+            value = value
+                .arithmetic(operator, other)
+                .map_err(runtime_error(expression))?;
+        }
+        Ok(value)
+    }
+
+    /// Evaluates a term of `expression`, which any error points at.
+    fn eval_term(
+        &mut self,
+        context: &mut Context,
+        term: &Term,
+        expression: &Expression,
+    ) -> Result<Value, CompileError> {
+        let mut value = self.eval_unary(context, &term.unary, expression)?;
+        for TermComp { operator, unary } in &term.components {
+            let other = self.eval_unary(context, unary, expression)?;
+            value = value
+                .arithmetic(operator, other)
+                .map_err(runtime_error(expression))?;
+        }
+        Ok(value)
+    }
+
+    fn eval_unary(
+        &mut self,
+        context: &mut Context,
+        unary: &Unary,
+        expression: &Expression,
+    ) -> Result<Value, CompileError> {
+        Ok(match unary {
+            Unary::Negate(unary) => match self.eval_unary(context, unary, expression)? {
+                Value::Int(val) => Value::Int(val.wrapping_neg()),
+                Value::Float(val) => Value::Float(-val),
+                Value::Str(_) => {
+                    return Err(runtime_error(expression)("Cannot negate a string".into()))
+                }
+            },
+            Unary::Primary(Primary::Float(val)) => Value::Float(*val),
+            Unary::Primary(Primary::Int(val)) => Value::Int(*val),
+            Unary::Primary(Primary::Str(text)) => Value::Str(text.clone()),
+            Unary::Primary(Primary::Ident(ident)) => match self.variables.get(ident) {
+                Some(value) => value.clone(),
+                None => Value::Int(0),
+            },
+            Unary::Primary(Primary::Group(expression)) => self.eval(context, expression)?,
+            Unary::Primary(Primary::Call { name, args, .. }) => self.call(context, name, args)?,
+        })
+    }
+}
+
+/// Turns an evaluation failure into a runtime error pointing at `expression`.
+fn runtime_error(expression: &Expression) -> impl FnOnce(String) -> CompileError + '_ {
+    |message| CompileError::runtime(message, expression.span)
+}
+
+/// Flattens nested statements into a list of ops with jumps, then points every GOTO
+/// and GOSUB at the op following its label.
+fn lower(statements: &[Statement]) -> Vec<Op<'_>> {
+    let mut ops = Vec::new();
+    let mut labels = HashMap::new();
+    lower_block(statements, &mut ops, &mut labels);
+    for op in ops.iter_mut() {
+        match op {
+            Op::Goto(ident) => *op = Op::Jump(labels[ident]),
+            Op::Gosub(ident, span) => *op = Op::Call(labels[ident], *span),
+            _ => {}
+        }
+    }
+    ops
+}
+
+fn lower_block<'a>(
+    statements: &'a [Statement],
+    ops: &mut Vec<Op<'a>>,
+    labels: &mut HashMap<&'a str, usize>,
+) {
+    for statement in statements {
+        match statement {
+            Statement::Let {
+                ident, expression, ..
+            } => ops.push(Op::Let(ident, expression)),
+            Statement::Print { items, newline, .. } => ops.push(Op::Print(items, *newline)),
+            Statement::Input { ident, .. } => ops.push(Op::Input(ident)),
+            Statement::Label { ident, .. } => {
+                labels.insert(ident, ops.len());
+            }
+            Statement::Goto { ident, .. } => ops.push(Op::Goto(ident)),
+            Statement::Gosub { ident, span, .. } => ops.push(Op::Gosub(ident, *span)),
+            Statement::Return {
+                value: Some(value), ..
+            } => ops.push(Op::ReturnValue(value)),
+            Statement::Return { span, .. } => ops.push(Op::Return(*span)),
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => {
+                // Every branch but the last jumps past the rest of the block when done.
+                let mut exits = Vec::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    let test = ops.len();
+                    ops.push(Op::JumpUnless(&branch.condition, 0));
+                    lower_block(&branch.statements, ops, labels);
+                    if index + 1 < branches.len() || else_statements.is_some() {
+                        exits.push(ops.len());
+                        ops.push(Op::Jump(0));
+                    }
+                    ops[test] = Op::JumpUnless(&branch.condition, ops.len());
+                }
+                if let Some(statements) = else_statements {
+                    lower_block(statements, ops, labels);
+                }
+                for exit in exits {
+                    ops[exit] = Op::Jump(ops.len());
+                }
+            }
+            Statement::For {
+                ident,
+                start,
+                end,
+                step,
+                statements,
+                ..
+            } => {
+                let start_op = ops.len();
+                ops.push(Op::ForStart(ident, start, end, step.as_deref()));
+                let top = ops.len();
+                ops.push(Op::ForTest(ident, start_op, 0));
+                lower_block(statements, ops, labels);
+                ops.push(Op::ForNext(ident, start_op));
+                ops.push(Op::Jump(top));
+                ops[top] = Op::ForTest(ident, start_op, ops.len());
+            }
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => {
+                let top = ops.len();
+                ops.push(Op::JumpUnless(condition, 0));
+                lower_block(statements, ops, labels);
+                ops.push(Op::Jump(top));
+                ops[top] = Op::JumpUnless(condition, ops.len());
+            }
+        }
+    }
+}
+
+/// Formats a float like the C backend's `tb_print_float`: the fewest significant digits
+/// that read back as the same value, in fixed notation unless the exponent is 21 or more,
+/// or -7 or less.
+fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    } else if value.is_infinite() {
+        return match value.is_sign_negative() {
+            true => "-inf".to_string(),
+            false => "inf".to_string(),
+        };
+    } else if value == 0.0 {
+        return "0".to_string();
+    }
+    // `{:e}` already gives the shortest digits that round-trip, as in `1.5e-7`.
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().unwrap();
+
+    let sign = if value < 0.0 { "-" } else { "" };
+    let text = if exponent >= 21 || exponent <= -7 {
+        let (first, rest) = digits.split_at(1);
+        let fraction = match rest.is_empty() {
+            true => String::new(),
+            false => format!(".{rest}"),
+        };
+        let exp_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{first}{fraction}e{exp_sign}{}", exponent.abs())
+    } else if exponent < 0 {
+        format!("0.{}{digits}", "0".repeat((-exponent - 1) as usize))
+    } else {
+        let point = exponent as usize + 1;
+        match digits.len() > point {
+            true => format!("{}.{}", &digits[..point], &digits[point..]),
+            false => format!("{digits}{}", "0".repeat(point - digits.len())),
+        }
+    };
+    format!("{sign}{text}")
+}
+
+/// Reads the next whitespace-separated word from `input`, or `None` at end of input.
+fn read_word(input: &mut dyn BufRead) -> std::io::Result<Option<String>> {
+    let mut word = Vec::new();
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let mut used = 0;
+        let mut done = false;
+        for byte in buf {
+            used += 1;
+            if byte.is_ascii_whitespace() {
+                if !word.is_empty() {
+                    done = true;
+                    break;
+                }
+            } else {
+                word.push(*byte);
+            }
+        }
+        input.consume(used);
+        if done {
+            break;
+        }
+    }
+    match word.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(String::from_utf8_lossy(&word).into_owned())),
+    }
+}
+
+/// Reads a number the way the C backend's `scanf("%lf")` does. Characters are taken for
+/// as long as they could still be the start of a number, however the input arrives, and
+/// the longest number at their front is the value: `1e+x` reads as 1 and leaves `x` for
+/// the next read. If there is no number there at all, the value is zero and the rest of
+/// the word is skipped, like the `scanf("%*s")` after it. `None` at end of input.
+fn read_number(input: &mut dyn BufRead) -> std::io::Result<Option<f64>> {
+    if !skip_whitespace(input)? {
+        return Ok(None);
+    }
+    let mut text = Vec::new();
+    while let Some(&byte) = input.fill_buf()?.first() {
+        text.push(byte);
+        if number_prefix(&text).1 < text.len() {
+            text.pop();
+            break;
+        }
+        input.consume(1);
+    }
+    let len = number_prefix(&text).0;
+    if len == 0 {
+        read_word(input)?;
+        return Ok(Some(0.0));
+    }
+    Ok(Some(parse_number(&text[..len])))
+}
+
+/// How much of `bytes` reads as a decimal or hexadecimal number: the length of the
+/// longest complete number at the front, and of the longest prefix that more input could
+/// still turn into one. Like `scanf`, a `0x` with no digits after it is no number at all.
+fn number_prefix(bytes: &[u8]) -> (usize, usize) {
+    let sign = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    for word in ["infinity", "nan"] {
+        let matched = bytes[sign..]
+            .iter()
+            .zip(word.bytes())
+            .take_while(|(byte, letter)| byte.to_ascii_lowercase() == *letter)
+            .count();
+        if matched > 0 {
+            let complete = match matched {
+                8 => 8,
+                3.. => 3,
+                _ => 0,
+            };
+            return (sign + complete, sign + matched);
+        }
+    }
+
+    let hex = matches!(bytes.get(sign..sign + 2), Some([b'0', b'x' | b'X']));
+    let (radix, exponent_marker) = match hex {
+        true => (16, b'p'),
+        false => (10, b'e'),
+    };
+    let digits = |from: usize, radix: u32| {
+        bytes[from..]
+            .iter()
+            .take_while(|byte| char::from(**byte).is_digit(radix))
+            .count()
+    };
+    let mut len = sign + if hex { 2 } else { 0 };
+    let mut mantissa = digits(len, radix);
+    len += mantissa;
+    if bytes.get(len) == Some(&b'.') {
+        let fraction = digits(len + 1, radix);
+        mantissa += fraction;
+        len += 1 + fraction;
+    }
+    if mantissa == 0 {
+        return (0, len);
+    }
+    let complete = len;
+    if bytes
+        .get(len)
+        .is_some_and(|byte| byte.to_ascii_lowercase() == exponent_marker)
+    {
+        let exponent_sign = usize::from(matches!(bytes.get(len + 1), Some(b'+' | b'-')));
+        let exponent = digits(len + 1 + exponent_sign, 10);
+        let viable = len + 1 + exponent_sign + exponent;
+        return match exponent {
+            0 => (complete, viable),
+            _ => (viable, viable),
+        };
+    }
+    (complete, len)
+}
+
+/// Value of a number that `number_prefix` read as complete.
+fn parse_number(text: &[u8]) -> f64 {
+    let text = String::from_utf8_lossy(text);
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text.strip_prefix('+').unwrap_or(&text)),
+    };
+    let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    else {
+        return text.parse().unwrap_or(0.0);
+    };
+    let (mantissa, exponent) = hex.split_once(['p', 'P']).unwrap_or((hex, "0"));
+    let mut value = 0.0;
+    let mut exponent = exponent.parse::<f64>().unwrap_or(0.0);
+    let mut fraction = false;
+    for chr in mantissa.chars() {
+        match chr.to_digit(16) {
+            Some(digit) => {
+                value = value * 16.0 + f64::from(digit);
+                if fraction {
+                    exponent -= 4.0;
+                }
+            }
+            None => fraction = true,
+        }
+    }
+    if value != 0.0 {
+        value *= exponent.exp2();
+    }
+    match negative {
+        true => -value,
+        false => value,
+    }
+}
+
+/// Consumes any whitespace at the front of `input`. Returns false at end of input.
+fn skip_whitespace(input: &mut dyn BufRead) -> std::io::Result<bool> {
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        let blank = buf
+            .iter()
+            .take_while(|byte| byte.is_ascii_whitespace())
+            .count();
+        let found = blank < buf.len();
+        input.consume(blank);
+        if found {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads the rest of the line after skipping any whitespace, like the C backend's
+/// `tb_input_str`, or `None` at end of input.
+fn read_line(input: &mut dyn BufRead) -> std::io::Result<Option<String>> {
+    if !skip_whitespace(input)? {
+        return Ok(None);
+    }
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line)?;
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Runs `source` with `input` on stdin and returns everything it printed.
+#[cfg(test)]
+pub(crate) fn run_source(source: &str, input: &str) -> String {
+    let program = build_source(source).unwrap();
+    let mut output = Vec::new();
+    Interpreter::new()
+        .run(&program, &mut input.as_bytes(), &mut output)
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_fib() {
+    assert_eq!(
+        run_source(include_str!("../../test_src/fib.tbsc"), "6\n"),
+        "How many fibonacci numbers do you want? 0\n1\n1\n2\n3\n5\n"
+    );
+}
+
+#[test]
+fn test_labels() {
+    assert_eq!(
+        run_source(include_str!("../../test_src/labels.tbsc"), ""),
+        "5\n4\n3\n2\n1\n0\nDING DING DING DING DING!!!\n"
+    );
+}
+
+#[test]
+fn test_minmax() {
+    assert_eq!(
+        run_source(
+            include_str!("../../test_src/minmax.tbsc"),
+            "4\n3 -1\n8.5 2\n"
+        ),
+        "Enter number of inputs: Min: -1\nMax: 8.5\nSum: 12.5\nAvg: 3.125\n"
+    );
+}
+
+#[test]
+fn test_conditions() {
+    let source = "\
+LET a = 2
+IF NOT a > 1 OR a == 2 AND a < 3 THEN
+    PRINT \"yes\"
+ENDIF
+IF NOT (a > 1 OR a == 2) THEN
+    PRINT \"no\"
+ENDIF
+";
+    assert_eq!(run_source(source, ""), "yes\n");
+    // The right operand is never evaluated, so the division can't fail.
+    let source = "\
+LET a = 0
+IF a != 0 AND 1 / a > 1 THEN
+    PRINT a
+ENDIF
+IF a == 0 OR 1 / a > 1 THEN
+    PRINT a
+ENDIF
+";
+    assert_eq!(run_source(source, ""), "0\n");
+}
+
+#[test]
+fn test_else_branches() {
+    let source = "\
+LET i = 0
+WHILE i < 4 REPEAT
+    IF i == 0 THEN
+        PRINT \"zero\"
+    ELSEIF i == 1 THEN
+        PRINT \"one\"
+    ELSEIF i == 2 THEN
+        PRINT \"two\"
+    ELSE
+        PRINT \"many\"
+    ENDIF
+    IF i > 1 THEN
+        PRINT i
+    ENDIF
+    LET i = i + 1
+ENDWHILE
+";
+    assert_eq!(run_source(source, ""), "zero\none\ntwo\n2\nmany\n3\n");
+}
+
+#[test]
+fn test_for_loops() {
+    let source = "\
+FOR i = 1 TO 3
+    PRINT i;
+NEXT i
+PRINT \" \"; i
+FOR x = 1 TO 0 STEP -0.25
+    PRINT x; \" \";
+NEXT x
+PRINT
+LET n = 2
+FOR j = n TO n * 2
+    LET n = 10
+    PRINT j;
+NEXT j
+PRINT
+FOR k = 5 TO 1
+    PRINT \"never\"
+NEXT k
+PRINT k
+";
+    assert_eq!(
+        run_source(source, ""),
+        "123 4\n1 0.75 0.5 0.25 0 \n234\n5\n"
+    );
+}
+
+#[test]
+fn test_gosub() {
+    let source = "\
+LET n = 0
+GOSUB count
+GOSUB count
+PRINT \"done\"
+GOTO end
+LABEL count
+LET n = n + 1
+IF n < 3 THEN
+    GOSUB count
+ENDIF
+PRINT n
+RETURN
+LABEL end
+";
+    assert_eq!(run_source(source, ""), "3\n3\n3\n4\ndone\n");
+}
+
+#[test]
+fn test_gosub_errors() {
+    let run = |source: &str| {
+        let program = build_source(source).unwrap();
+        Interpreter::new()
+            .run(&program, &mut "".as_bytes(), &mut Vec::new())
+            .unwrap_err()
+    };
+    let err = run("PRINT 1\nRETURN\n");
+    assert!(matches!(err, CompileError::Runtime { .. }));
+    assert_eq!(err.message(), "RETURN without GOSUB");
+    assert_eq!(err.span().map(|span| span.line), Some(2));
+    let err = run("LABEL forever\nGOSUB forever\n");
+    assert_eq!(err.message(), "Too many nested GOSUBs");
+    assert_eq!(err.span().map(|span| span.line), Some(2));
+}
+
+#[test]
+fn test_functions() {
+    assert_eq!(
+        run_source(include_str!("../../test_src/functions.tbsc"), "Ada\n"),
+        "Name? Hello, Ada! Hello, Ada! \n\
+         1! = 1\n2! = 2\n3! = 6\n4! = 24\n5! = 120\n6! = 720\n\
+         hypotenuse(3, 4) = 5\n\
+         i is still 7\n"
+    );
+    // Every call starts from zero, and leaves the caller's variables alone.
+    let source = "\
+FUNCTION sum(n)
+    IF n > 0 THEN
+        LET below = sum(n - 1)
+    ENDIF
+    RETURN below + n
+ENDFUNCTION
+FUNCTION bump()
+    IF 1 == 0 THEN
+        LET count = 0
+    ENDIF
+    LET count = count + 1
+    RETURN count
+ENDFUNCTION
+LET n = 10
+PRINT sum(3); \" \"; n; \" \"; bump(); bump()
+";
+    assert_eq!(run_source(source, ""), "6 10 11\n");
+}
+
+#[test]
+fn test_function_errors() {
+    let run = |source: &str| {
+        let program = build_source(source).unwrap();
+        Interpreter::new()
+            .run(&program, &mut "".as_bytes(), &mut Vec::new())
+            .unwrap_err()
+    };
+    // Debug builds need more stack for each call than a test thread has.
+    let err = std::thread::Builder::new()
+        .stack_size(16 << 20)
+        .spawn(move || {
+            run("FUNCTION forever(n)\nRETURN forever(n + 1)\nENDFUNCTION\nPRINT forever(0)\n")
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(err.message(), "Too many nested function calls");
+    assert_eq!(err.span().map(|span| span.line), Some(1));
+    let err = run("FUNCTION f()\nLET zero = 0\nRETURN 1 / zero\nENDFUNCTION\nPRINT f()\n");
+    assert_eq!(err.message(), "Integer division by zero");
+    assert_eq!(err.span().map(|span| span.line), Some(3));
+}
+
+#[test]
+fn test_input() {
+    // Garbage reads as zero; running out of input leaves the variable untouched.
+    assert_eq!(
+        run_source("LET a = 7\nINPUT a\nPRINT a\nINPUT a\nPRINT a\n", "abc"),
+        "0\n0\n"
+    );
+    assert_eq!(run_source("LET a = 7\nINPUT a\nPRINT a\n", ""), "7\n");
+}
+
+#[test]
+fn test_read_number() {
+    // However small the reads from the input, a number is read whole.
+    let input = "12345 -1.5e+2x 0x1p3 0X1.8 1e+x 0xg -inf nan .e1 7";
+    let expected = [
+        12345.0,
+        -150.0,
+        0.0,
+        8.0,
+        1.5,
+        1.0,
+        0.0,
+        0.0,
+        -f64::INFINITY,
+    ];
+    for capacity in [1, 2, 3, 1024] {
+        let mut input = std::io::BufReader::with_capacity(capacity, input.as_bytes());
+        let mut numbers = Vec::new();
+        while let Some(number) = read_number(&mut input).unwrap() {
+            numbers.push(number);
+        }
+        assert_eq!(numbers.len(), 12, "{capacity}");
+        assert_eq!(numbers[..9], expected, "{capacity}");
+        assert!(numbers[9].is_nan(), "{capacity}");
+        assert_eq!(numbers[10..], [0.0, 7.0], "{capacity}");
+    }
+}
+
+#[test]
+fn test_goto_into_and_out_of_blocks() {
+    let source = "\
+LET i = 0
+WHILE i < 10 REPEAT
+    LET i = i + 1
+    IF i == 3 THEN
+        GOTO done
+    ENDIF
+ENDWHILE
+LABEL done
+PRINT i
+IF i > 100 THEN
+    LABEL inside
+    PRINT \"inside\"
+    LET i = 0
+ENDIF
+IF i == 3 THEN
+    GOTO inside
+ENDIF
+";
+    assert_eq!(run_source(source, ""), "3\ninside\n");
+}
+
+#[test]
+fn test_line_numbers() {
+    let program = build_dialect(
+        include_str!("../../test_src/linenumbers.tbsc"),
+        crate::parser::Dialect::LineNumbers,
+    )
+    .unwrap();
+    let mut output = Vec::new();
+    Interpreter::new()
+        .run(&program, &mut "".as_bytes(), &mut output)
+        .unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "5 factorial is 120\n");
+}
+
+#[test]
+fn test_int_and_float_arithmetic() {
+    let source = "\
+LET i = 7
+LET f = 7.0
+PRINT i / 2
+PRINT f / 2
+PRINT -i / 2
+LET i = 2147483647
+PRINT i + 1
+";
+    assert_eq!(run_source(source, ""), "3\n3.5\n-3\n-2147483648\n");
+}
+
+#[test]
+fn test_integer_overflow() {
+    // Wraps around, as the C backend's `tb_add_int` and friends do.
+    let source = "\
+LET max = 2147483647
+LET min = -max - 1
+PRINT max + 1; \" \"; min - 1; \" \"; max * 2; \" \"; -min; \" \"; min / -1
+";
+    assert_eq!(
+        run_source(source, ""),
+        "-2147483648 2147483647 -2 -2147483648 -2147483648\n"
+    );
+}
+
+#[test]
+fn test_division_by_zero() {
+    let program = build_source("LET a = 0\nPRINT 1.0 / a\nPRINT 1 / a\n").unwrap();
+    let mut output = Vec::new();
+    let err = Interpreter::new()
+        .run(&program, &mut "".as_bytes(), &mut output)
+        .unwrap_err();
+    assert_eq!(String::from_utf8(output).unwrap(), "inf\n");
+    assert!(matches!(err, CompileError::Runtime { .. }));
+    assert_eq!(err.message(), "Integer division by zero");
+    assert_eq!(err.span().map(|span| span.line), Some(3));
+}
+
+#[test]
+fn test_strings() {
+    let source = "\
+LET greeting$ = \"Hello\"
+PRINT \"Name? \";
+INPUT name$
+INPUT age
+LET greeting$ = greeting$ + \", \" + name$
+IF name$ == \"Ada Lovelace\" THEN
+    PRINT greeting$ + \"!\"
+ENDIF
+IF greeting$ != \"\" THEN
+    PRINT age + 1
+ENDIF
+";
+    assert_eq!(
+        run_source(source, "  Ada Lovelace\r\n36\n"),
+        "Name? Hello, Ada Lovelace!\n37\n"
+    );
+}
+
+#[test]
+fn test_print_items() {
+    let source = "\
+LET n = 3
+PRINT \"n is \"; n; \"!\"
+PRINT \"a\", \"b\",
+PRINT n
+PRINT \"abcdefghijklmnop\", 1.5
+PRINT ; n;
+PRINT
+";
+    assert_eq!(
+        run_source(source, ""),
+        "n is 3!\n\
+         a             b             3\n\
+         abcdefghijklmnop            1.5\n\
+         3\n"
+    );
+}
+
+#[test]
+fn test_format_float() {
+    let cases = [
+        (0.5, "0.5"),
+        (-2.0, "-2"),
+        (100.0, "100"),
+        (0.1 + 0.2, "0.30000000000000004"),
+        (1.0 / 3.0, "0.3333333333333333"),
+        (123456.789, "123456.789"),
+        (1e20, "100000000000000000000"),
+        (1e21, "1e+21"),
+        (-1.5e300, "-1.5e+300"),
+        (0.000001, "0.000001"),
+        (1.5e-7, "1.5e-7"),
+        (5e-324, "5e-324"),
+        (-0.0, "0"),
+        (f64::NAN, "nan"),
+        (f64::NEG_INFINITY, "-inf"),
+    ];
+    for (value, expected) in cases {
+        assert_eq!(format_float(value), expected);
+    }
+}
@@ -1,5 +1,6 @@
-use std::iter::Peekable;
-use std::str::Chars;
+use crate::compiler::diagnostic::Span;
+use crate::compiler::error::CompileError;
+use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
@@ -16,123 +17,404 @@ pub enum Token {
     Lt,
     Lte,
     NotEquals,
+    LParen,
+    RParen,
+    Comma,
+    Semicolon,
     // TODO: Remaining Comparison operators
     // Identifiers
     Ident(String),
     // Data Types
     Int(i32),
-    Float(f32),
+    Float(f64),
     StrLit(String),
     // Keywords
+    And,
+    Else,
+    ElseIf,
+    EndFunction,
     EndIf,
     EndWhile,
+    For,
+    Function,
+    Gosub,
     Goto,
     If,
     Input,
     Label,
     Let,
+    Next,
+    Not,
+    Or,
     Print,
     Repeat,
+    Return,
+    Step,
     Then,
+    To,
     While,
     // Errors
     Invalid(String),
 }
 
-fn parse_strlit(input: &mut Peekable<Chars>) -> Result<Token, &'static str> {
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Add => write!(f, "'+'"),
+            Token::Sub => write!(f, "'-'"),
+            Token::Div => write!(f, "'/'"),
+            Token::Mul => write!(f, "'*'"),
+            Token::Assign => write!(f, "'='"),
+            Token::Equals => write!(f, "'=='"),
+            Token::NewLine => write!(f, "newline"),
+            Token::Gt => write!(f, "'>'"),
+            Token::Gte => write!(f, "'>='"),
+            Token::Lt => write!(f, "'<'"),
+            Token::Lte => write!(f, "'<='"),
+            Token::NotEquals => write!(f, "'!='"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Semicolon => write!(f, "';'"),
+            Token::Ident(name) => write!(f, "identifier '{name}'"),
+            Token::Int(val) => write!(f, "number {val}"),
+            Token::Float(val) => write!(f, "number {val}"),
+            Token::StrLit(_) => write!(f, "string literal"),
+            Token::And => write!(f, "'AND'"),
+            Token::Else => write!(f, "'ELSE'"),
+            Token::ElseIf => write!(f, "'ELSEIF'"),
+            Token::EndFunction => write!(f, "'ENDFUNCTION'"),
+            Token::EndIf => write!(f, "'ENDIF'"),
+            Token::EndWhile => write!(f, "'ENDWHILE'"),
+            Token::For => write!(f, "'FOR'"),
+            Token::Function => write!(f, "'FUNCTION'"),
+            Token::Gosub => write!(f, "'GOSUB'"),
+            Token::Goto => write!(f, "'GOTO'"),
+            Token::If => write!(f, "'IF'"),
+            Token::Input => write!(f, "'INPUT'"),
+            Token::Label => write!(f, "'LABEL'"),
+            Token::Let => write!(f, "'LET'"),
+            Token::Next => write!(f, "'NEXT'"),
+            Token::Not => write!(f, "'NOT'"),
+            Token::Or => write!(f, "'OR'"),
+            Token::Print => write!(f, "'PRINT'"),
+            Token::Repeat => write!(f, "'REPEAT'"),
+            Token::Return => write!(f, "'RETURN'"),
+            Token::Step => write!(f, "'STEP'"),
+            Token::Then => write!(f, "'THEN'"),
+            Token::To => write!(f, "'TO'"),
+            Token::While => write!(f, "'WHILE'"),
+            Token::Invalid(text) => write!(f, "invalid token '{text}'"),
+        }
+    }
+}
+
+/// A token along with the region of source code it was scanned from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Character cursor over the source code which keeps track of how far it has read,
+/// so that every token can be given a span.
+struct Scanner<'a> {
+    source: &'a str,
+    offset: usize,
+    line: u32,
+    col: u32,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Scanner<'a> {
+        Scanner {
+            source,
+            offset: 0,
+            line: 1,
+            col: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(chr) if func(&chr) => self.next(),
+            _ => None,
+        }
+    }
+
+    /// Empty span at the current position, to be extended with `span_from`.
+    fn position(&self) -> Span {
+        Span::new(self.offset, self.offset, self.line, self.col)
+    }
+
+    /// Span from `start` up to the current position.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
+        }
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let chr = self.peek()?;
+        self.offset += chr.len_utf8();
+        if chr == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(chr)
+    }
+}
+
+/// Parses a string literal whose opening quote is at `start`, already consumed.
+fn parse_strlit(input: &mut Scanner, start: Span) -> Result<Token, CompileError> {
     let mut strlit = String::new();
-    while let Some(chr) = input.next() {
-        if chr == '\\' {
-            match input.peek() {
-                Some('"') => strlit.push(input.next().unwrap()),
-                Some('\\') => strlit.push(input.next().unwrap()),
-                _ => strlit.push(chr),
+    loop {
+        // Spelled out, since `input.position()` would find `Iterator::position` first.
+        let escape_start = Scanner::position(input);
+        match input.next() {
+            Some('"') => return Ok(Token::StrLit(strlit)),
+            Some('\\') => strlit.push(parse_escape(input, escape_start)?),
+            Some(chr) => strlit.push(chr),
+            None => {
+                return Err(CompileError::lexical(
+                    "unterminated string literal!",
+                    input.span_from(start),
+                ))
+            }
+        }
+    }
+}
+
+/// Parses the rest of an escape sequence starting at the backslash at `start`:
+/// `\"`, `\\`, `\n`, `\t`, or `\u{...}` with 1 to 6 hex digits.
+fn parse_escape(input: &mut Scanner, start: Span) -> Result<char, CompileError> {
+    match input.next() {
+        Some('"') => Ok('"'),
+        Some('\\') => Ok('\\'),
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('u') => {
+            let malformed = |input: &Scanner| {
+                CompileError::lexical(
+                    "Malformed unicode escape, expected '\\u{...}' with 1 to 6 hex digits",
+                    input.span_from(start),
+                )
+            };
+            if input.next_if(|chr| *chr == '{').is_none() {
+                return Err(malformed(input));
+            }
+            let mut digits = String::new();
+            while let Some(digit) = input.next_if(|chr| chr.is_ascii_hexdigit()) {
+                digits.push(digit);
+            }
+            if input.next_if(|chr| *chr == '}').is_none() || digits.is_empty() || digits.len() > 6 {
+                return Err(malformed(input));
+            }
+            // Strings end up as C strings, which can't hold a NUL.
+            match u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+            {
+                Some(chr) if chr != '\0' => Ok(chr),
+                _ => Err(CompileError::lexical(
+                    format!("'\\u{{{digits}}}' is not a valid character in a string"),
+                    input.span_from(start),
+                )),
             }
-        } else if chr == '"' {
-            return Ok(Token::StrLit(strlit));
-        } else {
-            strlit.push(chr);
         }
+        Some(chr) => Err(CompileError::lexical(
+            format!("Unknown escape sequence '\\{chr}', write '\\\\' for a backslash"),
+            input.span_from(start),
+        )),
+        None => Err(CompileError::lexical(
+            "unterminated string literal!",
+            input.span_from(start),
+        )),
     }
-    Err("unterminated string literal!")
 }
 
 #[test]
 fn test_parse_strlit() {
-    let mut input = "I am quite hungry\"".chars().peekable();
+    let mut input = Scanner::new("I am quite hungry\"");
     assert_eq!(
-        parse_strlit(&mut input),
+        parse_strlit(&mut input, Span::default()),
         Ok(Token::StrLit("I am quite hungry".to_string()))
     );
-    let mut input = "He said, \\\"Feed Me!\\\" hungrily\"".chars().peekable();
+    let mut input = Scanner::new("He said, \\\"Feed Me!\\\" hungrily\"");
     assert_eq!(
-        parse_strlit(&mut input),
+        parse_strlit(&mut input, Span::default()),
         Ok(Token::StrLit("He said, \"Feed Me!\" hungrily".to_string()))
     );
-    let mut input = "BACON!".chars().peekable();
+    let mut input = Scanner::new("BACON!");
+    assert_eq!(
+        parse_strlit(&mut input, Span::new(0, 0, 1, 1)),
+        Err(CompileError::lexical(
+            "unterminated string literal!",
+            Span::new(0, 6, 1, 1)
+        ))
+    );
+}
+
+#[test]
+fn test_escapes() {
+    assert_eq!(
+        lex_tokens(r#""100%\tdone\n\u{48}\u{e9}\u{1F600}\\""#),
+        Ok(vec![Token::StrLit(
+            "100%\tdone\nH\u{e9}\u{1F600}\\".to_string()
+        )])
+    );
+    let error = |source: &str| {
+        let err = lex_source(source).unwrap_err();
+        (err.message().to_string(), err.span().unwrap())
+    };
+    assert_eq!(
+        error(r#"PRINT "C:\path""#),
+        (
+            "Unknown escape sequence '\\p', write '\\\\' for a backslash".to_string(),
+            Span::new(9, 11, 1, 10)
+        )
+    );
+    assert_eq!(
+        error(r#""\u{}""#).0,
+        "Malformed unicode escape, expected '\\u{...}' with 1 to 6 hex digits"
+    );
+    assert_eq!(
+        error(r#""\u41""#).0,
+        "Malformed unicode escape, expected '\\u{...}' with 1 to 6 hex digits"
+    );
+    assert_eq!(
+        error(r#""\u{D800}""#),
+        (
+            "'\\u{D800}' is not a valid character in a string".to_string(),
+            Span::new(1, 9, 1, 2)
+        )
+    );
     assert_eq!(
-        parse_strlit(&mut input),
-        Err("unterminated string literal!")
+        error(r#""\u{0}""#).0,
+        "'\\u{0}' is not a valid character in a string"
     );
 }
-fn parse_ident(first: char, input: &mut Peekable<Chars>) -> Option<Token> {
+fn parse_ident(first: char, input: &mut Scanner) -> Option<Token> {
     if first.is_alphabetic() {
         let mut matched = String::new();
         matched.push(first);
-        while let Some(next) = input.next_if(|chr| chr.is_alphanumeric() || *chr == '_') {
-            // dbg!(next);
-            matched.push(next);
-        }
-
-        return Some(Token::Ident(matched));
+        Some(finish_ident(matched, input))
     } else {
-        return None;
+        None
     }
 }
 
+/// Reads the rest of an identifier that starts with `matched`.
+fn finish_ident(mut matched: String, input: &mut Scanner) -> Token {
+    while let Some(next) = input.next_if(|chr| chr.is_alphanumeric() || *chr == '_') {
+        matched.push(next);
+    }
+    // String variables are marked with a trailing `$`.
+    if let Some(dollar) = input.next_if(|chr| *chr == '$') {
+        matched.push(dollar);
+    }
+    Token::Ident(matched)
+}
+
 /// parses an integer or floating point number
-fn parse_num(first: char, input: &mut Peekable<Chars>) -> Option<Token> {
-    if first.is_digit(10) {
+fn parse_num(first: char, input: &mut Scanner, start: Span) -> Result<Option<Token>, CompileError> {
+    if first.is_ascii_digit() {
         let mut is_float = false;
         let mut digits = String::new();
         digits.push(first);
-        while let Some(digit) = input.next_if(|c| (c.is_digit(10) | (*c == '.'))) {
+        while let Some(digit) = input.next_if(|c| c.is_ascii_digit() | (*c == '.')) {
             if digit == '.' {
                 is_float = true;
             }
             digits.push(digit);
         }
         return match is_float {
-            true => match digits.parse::<f32>() {
-                Ok(flt) => Some(Token::Float(flt)),
-                _ => Some(Token::Invalid(digits)),
+            true => match digits.parse::<f64>() {
+                Ok(flt) => Ok(Some(Token::Float(flt))),
+                _ => Ok(Some(Token::Invalid(digits))),
             },
             false => match digits.parse::<i32>() {
-                Ok(int) => Some(Token::Int(int)),
-                _ => None,
+                Ok(int) => Ok(Some(Token::Int(int))),
+                _ => Err(CompileError::lexical(
+                    format!("Integer literal {digits} is too large, write {digits}.0 for a float"),
+                    input.span_from(start),
+                )),
             },
         };
     }
-    None
+    Ok(None)
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_parse_num() {
     let input = "15";
-    let mut chars = input.chars().peekable();
+    let mut chars = Scanner::new(input);
+    let start = chars.position();
     assert_eq!(
-        parse_num(chars.next().unwrap(), &mut chars),
-        Some(Token::Int(15))
+        parse_num(chars.next().unwrap(), &mut chars, start),
+        Ok(Some(Token::Int(15)))
     );
     let input = "3.1415";
-    let mut chars = input.chars().peekable();
+    let mut chars = Scanner::new(input);
+    let start = chars.position();
+    assert_eq!(
+        parse_num(chars.next().unwrap(), &mut chars, start),
+        Ok(Some(Token::Float(3.1415)))
+    );
+    assert_eq!(
+        lex_tokens("PRINT 2147483647"),
+        Ok(vec![Token::Print, Token::Int(2147483647)])
+    );
     assert_eq!(
-        parse_num(chars.next().unwrap(), &mut chars),
-        Some(Token::Float(3.1415))
+        lex_source("PRINT 1\nPRINT 3000000000\n"),
+        Err(CompileError::lexical(
+            "Integer literal 3000000000 is too large, write 3000000000.0 for a float",
+            Span::new(14, 24, 2, 7)
+        ))
     );
 }
 
-fn parse_keyword(first: char, input: &mut Peekable<Chars>) -> Option<Token> {
+/// Every keyword the lexer recognises, used to suggest fixes for misspellings.
+pub const KEYWORDS: &[&str] = &[
+    "AND",
+    "ELSE",
+    "ELSEIF",
+    "ENDFUNCTION",
+    "ENDIF",
+    "ENDWHILE",
+    "FOR",
+    "FUNCTION",
+    "GOSUB",
+    "GOTO",
+    "IF",
+    "INPUT",
+    "LABEL",
+    "LET",
+    "NEXT",
+    "NOT",
+    "OR",
+    "PRINT",
+    "REPEAT",
+    "RETURN",
+    "STEP",
+    "THEN",
+    "TO",
+    "WHILE",
+];
+
+fn parse_keyword(first: char, input: &mut Scanner) -> Option<Token> {
     // All keywords are in ALL_CAPS
     if !first.is_uppercase() {
         return None;
@@ -142,101 +424,221 @@ fn parse_keyword(first: char, input: &mut Peekable<Chars>) -> Option<Token> {
     while let Some(next) = input.next_if(|chr| chr.is_uppercase()) {
         keyword.push(next)
     }
+    // A keyword must end where the run of capitals does: `TOtal` and `ORx$` are identifiers
+    if input
+        .peek()
+        .is_some_and(|chr| chr.is_alphanumeric() || chr == '_' || chr == '$')
+    {
+        return Some(finish_ident(keyword, input));
+    }
 
     match keyword.as_str() {
+        "AND" => Some(Token::And),
+        "ELSE" => Some(Token::Else),
+        "ELSEIF" => Some(Token::ElseIf),
+        "ENDFUNCTION" => Some(Token::EndFunction),
         "ENDIF" => Some(Token::EndIf),
         "ENDWHILE" => Some(Token::EndWhile),
+        "FOR" => Some(Token::For),
+        "FUNCTION" => Some(Token::Function),
+        "GOSUB" => Some(Token::Gosub),
         "GOTO" => Some(Token::Goto),
         "IF" => Some(Token::If),
         "INPUT" => Some(Token::Input),
         "LABEL" => Some(Token::Label),
         "LET" => Some(Token::Let),
+        "NEXT" => Some(Token::Next),
+        "NOT" => Some(Token::Not),
+        "OR" => Some(Token::Or),
         "PRINT" => Some(Token::Print),
         "REPEAT" => Some(Token::Repeat),
+        "RETURN" => Some(Token::Return),
+        "STEP" => Some(Token::Step),
         "THEN" => Some(Token::Then),
+        "TO" => Some(Token::To),
         "WHILE" => Some(Token::While),
         // Default case is we don't match a keyword. In that case we must have an identifier
-        // that happens to be all caps
-        _ => Some(Token::Ident(keyword)),
+        // that happens to start with capitals, like `A$` or `Total`
+        _ => Some(finish_ident(keyword, input)),
+    }
+}
+
+#[test]
+fn test_keyword_list() {
+    for keyword in KEYWORDS {
+        let mut chars = Scanner::new(keyword);
+        let token = parse_keyword(chars.next().unwrap(), &mut chars);
+        assert!(!matches!(token, Some(Token::Ident(_))), "{keyword}");
     }
 }
 
 #[test]
 fn test_parse_keyword() {
     let input = "IF something == 0 ENDIF";
-    let mut chars = input.chars().peekable();
+    let mut chars = Scanner::new(input);
     assert_eq!(
         parse_keyword(chars.next().unwrap(), &mut chars),
         Some(Token::If)
     );
+    assert_eq!(
+        lex_tokens("NOT a > 1 AND b < 2 OR c").unwrap(),
+        vec![
+            Token::Not,
+            Token::Ident("a".to_string()),
+            Token::Gt,
+            Token::Int(1),
+            Token::And,
+            Token::Ident("b".to_string()),
+            Token::Lt,
+            Token::Int(2),
+            Token::Or,
+            Token::Ident("c".to_string()),
+        ]
+    );
+    assert_eq!(
+        lex_tokens("FOR i = 10 TO 1 STEP -1\nNEXT i").unwrap(),
+        vec![
+            Token::For,
+            Token::Ident("i".to_string()),
+            Token::Assign,
+            Token::Int(10),
+            Token::To,
+            Token::Int(1),
+            Token::Step,
+            Token::Sub,
+            Token::Int(1),
+            Token::NewLine,
+            Token::Next,
+            Token::Ident("i".to_string()),
+        ]
+    );
+    assert_eq!(
+        lex_tokens("INPUT NAME$\nLET A$ = Ab + A1").unwrap(),
+        vec![
+            Token::Input,
+            Token::Ident("NAME$".to_string()),
+            Token::NewLine,
+            Token::Let,
+            Token::Ident("A$".to_string()),
+            Token::Assign,
+            Token::Ident("Ab".to_string()),
+            Token::Add,
+            Token::Ident("A1".to_string()),
+        ]
+    );
+    assert_eq!(
+        lex_tokens("LET TOtal = IF_1 + TO2\nLET ORx$ = LETTER$").unwrap(),
+        vec![
+            Token::Let,
+            Token::Ident("TOtal".to_string()),
+            Token::Assign,
+            Token::Ident("IF_1".to_string()),
+            Token::Add,
+            Token::Ident("TO2".to_string()),
+            Token::NewLine,
+            Token::Let,
+            Token::Ident("ORx$".to_string()),
+            Token::Assign,
+            Token::Ident("LETTER$".to_string()),
+        ]
+    );
+    assert_eq!(
+        lex_tokens("FUNCTION f(a, b$)\nENDFUNCTION").unwrap(),
+        vec![
+            Token::Function,
+            Token::Ident("f".to_string()),
+            Token::LParen,
+            Token::Ident("a".to_string()),
+            Token::Comma,
+            Token::Ident("b$".to_string()),
+            Token::RParen,
+            Token::NewLine,
+            Token::EndFunction,
+        ]
+    );
 }
 
-pub fn lex_source(input: &str) -> Result<Vec<Token>, &str> {
-    let mut tokens: Vec<Token> = vec![];
-    let mut chars = input.chars().peekable();
-    let mut _line_num: u32 = 1; // eventually useful for error handling
-
-    while let Some(next) = chars.next() {
-        match next {
-            '+' => tokens.push(Token::Add),
-            '-' => tokens.push(Token::Sub),
-            '*' => tokens.push(Token::Mul),
-            '/' => tokens.push(Token::Div),
+pub fn lex_source(input: &str) -> Result<Vec<SpannedToken>, CompileError> {
+    let mut tokens: Vec<SpannedToken> = vec![];
+    let mut chars = Scanner::new(input);
+
+    loop {
+        let start = chars.position();
+        let Some(next) = chars.next() else {
+            break;
+        };
+        let token = match next {
+            '+' => Token::Add,
+            '-' => Token::Sub,
+            '*' => Token::Mul,
+            '/' => Token::Div,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
             '>' => match chars.peek() {
                 Some('=') => {
-                    tokens.push(Token::Gte);
                     chars.next();
+                    Token::Gte
                 }
-                _ => tokens.push(Token::Gt),
+                _ => Token::Gt,
             },
             '<' => match chars.peek() {
                 Some('=') => {
-                    tokens.push(Token::Lte);
                     chars.next();
+                    Token::Lte
                 }
-                _ => tokens.push(Token::Lt),
+                _ => Token::Lt,
             },
-            '\n' => {
-                tokens.push(Token::NewLine);
-                _line_num += 1;
-            }
+            '\n' => Token::NewLine,
             ' ' => continue,
             '\t' => continue,
             '=' => match chars.peek() {
                 Some('=') => {
-                    tokens.push(Token::Equals);
                     chars.next();
+                    Token::Equals
                 }
-                _ => tokens.push(Token::Assign),
+                _ => Token::Assign,
             },
-            '!' => match chars.next() {
+            '!' => match chars.peek() {
                 Some('=') => {
-                    tokens.push(Token::NotEquals);
+                    chars.next();
+                    Token::NotEquals
                 }
-                _ => tokens.push(Token::Invalid(format!("!{:?}", chars.next()))),
+                _ => Token::Invalid("!".to_string()),
             },
-            '"' => tokens.push(parse_strlit(&mut chars)?),
+            '"' => parse_strlit(&mut chars, start)?,
             _ => {
                 if let Some(token) = parse_keyword(next, &mut chars) {
-                    tokens.push(token);
+                    token
                 } else if let Some(token) = parse_ident(next, &mut chars) {
-                    tokens.push(token);
-                } else if let Some(token) = parse_num(next, &mut chars) {
-                    tokens.push(token);
+                    token
+                } else if let Some(token) = parse_num(next, &mut chars, start)? {
+                    token
                 } else {
-                    tokens.push(Token::Invalid(next.to_string()));
+                    Token::Invalid(next.to_string())
                 }
             }
-        }
+        };
+        tokens.push(SpannedToken {
+            token,
+            span: chars.span_from(start),
+        });
     }
 
     Ok(tokens)
 }
 
+/// Lexes `input` and throws away the spans, to keep test expectations readable.
+#[cfg(test)]
+fn lex_tokens(input: &str) -> Result<Vec<Token>, CompileError> {
+    lex_source(input).map(|tokens| tokens.into_iter().map(|tok| tok.token).collect())
+}
+
 #[test]
 fn test_parse_tokens() {
     assert_eq!(
-        lex_source("+-=+=="),
+        lex_tokens("+-=+=="),
         Ok(vec![
             Token::Add,
             Token::Sub,
@@ -247,7 +649,7 @@ fn test_parse_tokens() {
     );
 
     assert_eq!(
-        lex_source("IF something == BANANA ENDIF grape"),
+        lex_tokens("IF something == BANANA ENDIF grape"),
         Ok(vec![
             Token::If,
             Token::Ident("something".to_string()),
@@ -259,7 +661,7 @@ fn test_parse_tokens() {
     );
 
     assert_eq!(
-        lex_source("WHILE something == 0 11.11 ENDWHILE"),
+        lex_tokens("WHILE something == 0 11.11 ENDWHILE"),
         Ok(vec![
             Token::While,
             Token::Ident("something".to_string()),
@@ -272,7 +674,7 @@ fn test_parse_tokens() {
 
     assert_eq!(
         // matches https://austinhenley.com/blog/teenytinycompiler1.html
-        lex_source("IF+-123 foo*THEN/\n"),
+        lex_tokens("IF+-123 foo*THEN/\n"),
         Ok(vec![
             Token::If,
             Token::Add,
@@ -287,10 +689,89 @@ fn test_parse_tokens() {
     );
 }
 
+#[test]
+fn test_token_spans() {
+    let tokens = lex_source("LET a = 10\nPRINT \"hi\"\n").unwrap();
+    let spans: Vec<Span> = tokens.iter().map(|tok| tok.span).collect();
+    assert_eq!(
+        spans,
+        vec![
+            Span::new(0, 3, 1, 1),
+            Span::new(4, 5, 1, 5),
+            Span::new(6, 7, 1, 7),
+            Span::new(8, 10, 1, 9),
+            Span::new(10, 11, 1, 11),
+            Span::new(11, 16, 2, 1),
+            Span::new(17, 21, 2, 7),
+            Span::new(21, 22, 2, 11),
+        ]
+    );
+}
+
+#[test]
+fn test_lone_bang() {
+    // Whatever follows a `!` that isn't part of `!=` is left to be lexed on its own
+    let tokens = lex_source("PRINT !\nPRINT !a != 1").unwrap();
+    let tokens: Vec<(Token, Span)> = tokens
+        .into_iter()
+        .map(|tok| (tok.token, tok.span))
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            (Token::Print, Span::new(0, 5, 1, 1)),
+            (Token::Invalid("!".to_string()), Span::new(6, 7, 1, 7)),
+            (Token::NewLine, Span::new(7, 8, 1, 8)),
+            (Token::Print, Span::new(8, 13, 2, 1)),
+            (Token::Invalid("!".to_string()), Span::new(14, 15, 2, 7)),
+            (Token::Ident("a".to_string()), Span::new(15, 16, 2, 8)),
+            (Token::NotEquals, Span::new(17, 19, 2, 10)),
+            (Token::Int(1), Span::new(20, 21, 2, 13)),
+        ]
+    );
+}
+
+#[test]
+fn test_unterminated_strlit_span() {
+    assert_eq!(
+        lex_source("LET a = 1\nPRINT \"oops"),
+        Err(CompileError::lexical(
+            "unterminated string literal!",
+            Span::new(16, 21, 2, 7)
+        ))
+    );
+}
+
+#[test]
+fn test_parens() {
+    assert_eq!(
+        lex_tokens("(a+(1))"),
+        Ok(vec![
+            Token::LParen,
+            Token::Ident("a".to_string()),
+            Token::Add,
+            Token::LParen,
+            Token::Int(1),
+            Token::RParen,
+            Token::RParen,
+        ])
+    );
+    assert_eq!(
+        lex_tokens("PRINT a, b;"),
+        Ok(vec![
+            Token::Print,
+            Token::Ident("a".to_string()),
+            Token::Comma,
+            Token::Ident("b".to_string()),
+            Token::Semicolon,
+        ])
+    );
+}
+
 #[test]
 fn test_parse_ident() {
     let input = "key = valu3";
-    let mut chars = input.chars().peekable();
+    let mut chars = Scanner::new(input);
     let first = chars.next().unwrap();
     assert_eq!(
         parse_ident(first, &mut chars),
@@ -303,4 +784,10 @@ fn test_parse_ident() {
         parse_ident(chars.next().unwrap(), &mut chars),
         Some(Token::Ident("valu3".to_string()))
     );
+    let mut chars = Scanner::new("ame$+");
+    assert_eq!(
+        parse_ident('n', &mut chars),
+        Some(Token::Ident("name$".to_string()))
+    );
+    assert_eq!(chars.next(), Some('+'));
 }
@@ -1,14 +1,209 @@
 #![allow(dead_code, unused_variables)]
-use crate::lexer::Token;
-use std::collections::HashSet;
-use std::iter::Peekable;
+use crate::compiler::diagnostic::{Diagnostic, Span};
+use crate::compiler::error::CompileError;
+#[cfg(test)]
+use crate::compiler::testing::{build_dialect, build_source};
+use crate::compiler::types::{self, Type};
+use crate::lexer::{SpannedToken, Token};
+use std::collections::{HashMap, HashSet};
+
+/// Cursor over the lexed tokens. Iterates over the bare `Token`s like a `Peekable` would,
+/// while keeping their spans at hand for error reporting.
+#[derive(Clone)]
+pub struct TokenStream<'a> {
+    tokens: &'a [SpannedToken],
+    pos: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(tokens: &'a [SpannedToken]) -> TokenStream<'a> {
+        TokenStream { tokens, pos: 0 }
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|tok| &tok.token)
+    }
+
+    pub fn next_if(&mut self, func: impl FnOnce(&&'a Token) -> bool) -> Option<&'a Token> {
+        match self.peek() {
+            Some(token) if func(&token) => self.next(),
+            _ => None,
+        }
+    }
+
+    /// Span of the next token, or an empty span just past the end of the input.
+    pub fn peek_span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(tok) => tok.span,
+            None => self.eof_span(),
+        }
+    }
+
+    /// Span of the token most recently consumed.
+    pub fn prev_span(&self) -> Span {
+        match self.pos.checked_sub(1).and_then(|pos| self.tokens.get(pos)) {
+            Some(tok) => tok.span,
+            None => self.peek_span(),
+        }
+    }
+
+    /// The column is the last token's plus its length in bytes, which overstates it after
+    /// non-ASCII text; `Diagnostic::render` recounts it from the source.
+    fn eof_span(&self) -> Span {
+        match self.tokens.last() {
+            Some(tok) => {
+                let width = (tok.span.end - tok.span.start) as u32;
+                Span::new(
+                    tok.span.end,
+                    tok.span.end,
+                    tok.span.line,
+                    tok.span.col + width,
+                )
+            }
+            None => Span::new(0, 0, 1, 1),
+        }
+    }
+
+    /// Syntax error at the next token, saying what was `expected` and what was found instead.
+    fn unexpected(&self, expected: &str) -> CompileError {
+        CompileError::syntax(
+            format!("{expected}, found {}", describe(self.peek())),
+            self.peek_span(),
+        )
+    }
+
+    /// Consumes the next token if it is `expected`, otherwise reports `message` at it.
+    fn expect(&mut self, expected: &Token, message: &str) -> Result<(), CompileError> {
+        match self.peek() {
+            Some(token) if token == expected => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected(message)),
+        }
+    }
+
+    /// Whether the next token is followed by a `(`, as in the call `f(x)`. With space in
+    /// between, as in `f (x)`, it is only a call if `variables` has no such name, since a
+    /// variable can't be followed by a group anyway.
+    fn opens_call(&self, variables: &HashSet<String>) -> bool {
+        match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(name), Some(paren)) if paren.token == Token::LParen => {
+                paren.span.start == name.span.end
+                    || !matches!(&name.token, Token::Ident(ident) if variables.contains(ident))
+            }
+            _ => false,
+        }
+    }
+
+    /// Consumes an identifier and returns its name, otherwise reports `message` at the token.
+    fn expect_ident(&mut self, message: &str) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                self.next();
+                Ok(name.clone())
+            }
+            _ => Err(self.unexpected(message)),
+        }
+    }
+}
+
+impl TokenStream<'_> {
+    /// Skips the remainder of a statement that failed to parse, leaving the stream at the
+    /// start of the next one. With `in_block` set, the skip runs to the end of the line
+    /// holding the ENDIF/ENDWHILE/NEXT/ENDFUNCTION that closes the current block.
+    fn synchronize(&mut self, in_block: bool) {
+        if !in_block && self.pos > 0 && self.tokens[self.pos - 1].token == Token::NewLine {
+            // The failed statement already consumed its newline.
+            return;
+        }
+        let mut depth = usize::from(in_block);
+        for token in self.by_ref() {
+            match token {
+                Token::If | Token::While | Token::For | Token::Function if depth > 0 => depth += 1,
+                Token::EndIf | Token::EndWhile | Token::Next | Token::EndFunction if depth > 0 => {
+                    depth -= 1
+                }
+                Token::NewLine if depth == 0 => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Describes a token that may be missing because the input ran out.
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(token) => token.to_string(),
+        None => "end of file".to_string(),
+    }
+}
+
+impl<'a> Iterator for TokenStream<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos).map(|tok| &tok.token)?;
+        self.pos += 1;
+        Some(token)
+    }
+}
+
+/// Stop collecting syntax errors after this many, since later ones are
+/// increasingly likely to be knock-on effects of earlier ones.
+pub const MAX_ERRORS: usize = 20;
+
+/// The flavours of Tiny BASIC source the parser understands.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Dialect {
+    /// Jump targets are declared with `LABEL name`.
+    #[default]
+    Labels,
+    /// Classic listings such as `10 PRINT "HI"` / `20 GOTO 10`, where a number at the
+    /// start of a line labels it. `LABEL` still works alongside the numbers.
+    LineNumbers,
+}
 
 #[derive(Debug)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// Variables of the main program. Functions have symbols of their own.
+    pub symbols: HashSet<String>,
+    /// Type of every symbol, and of what each function returns, inferred once the whole
+    /// program has been parsed. Any types already here are kept, though they may widen.
+    pub types: HashMap<String, Type>,
+    pub functions: Vec<Function>,
+    pub warnings: Vec<Diagnostic>,
+    pub dialect: Dialect,
+    labels_declared: HashMap<String, Span>,
+    /// Target of every GOTO and GOSUB, where it was named, and which of the two it was.
+    labels_gotoed: Vec<(String, Span, &'static str)>,
+    /// Variables of the FOR loops being parsed, innermost last.
+    open_loops: Vec<String>,
+    /// Name of the function whose body is being parsed, if any.
+    in_function: Option<String>,
+    errors: Vec<CompileError>,
+}
+
+/// A `FUNCTION name(a, b)` definition. The body only sees the parameters and its own
+/// variables, which start from zero on every call.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub statements: Vec<Statement>,
+    /// The parameters and every variable of the body.
     pub symbols: HashSet<String>,
-    labels_declared: HashSet<String>,
-    labels_gotoed: HashSet<String>,
+    /// Type of every symbol, and of what each function returns.
+    pub types: HashMap<String, Type>,
+    /// Span of the name in the definition.
+    pub span: Span,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Program {
@@ -16,213 +211,802 @@ impl Program {
         Program {
             statements: Vec::new(),
             symbols: HashSet::new(),
-            labels_declared: HashSet::new(),
-            labels_gotoed: HashSet::new(),
+            types: HashMap::new(),
+            functions: Vec::new(),
+            warnings: Vec::new(),
+            dialect: Dialect::default(),
+            labels_declared: HashMap::new(),
+            labels_gotoed: Vec::new(),
+            open_loops: Vec::new(),
+            in_function: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Parses the whole program, carrying on past bad statements so that every
+    /// error in the source (up to `MAX_ERRORS`) is reported at once.
+    pub fn build(&mut self, tokens: Vec<SpannedToken>) -> Result<(), Vec<CompileError>> {
+        let mut tokens = TokenStream::new(&tokens);
+        match Program::get_statements(self, &mut tokens, &[]) {
+            Ok((statements, _)) => self.statements = statements,
+            Err(err) => self.record_error(err),
+        }
+        self.resolve_labels();
+        if self.errors.is_empty() {
+            let check = types::infer(
+                &self.statements,
+                &self.symbols,
+                &self.types,
+                &self.functions,
+            );
+            self.types = check.types;
+            for (function, types) in self.functions.iter_mut().zip(check.function_types) {
+                function.types = types;
+            }
+            self.warnings.extend(check.warnings);
+            for err in check.errors {
+                self.record_error(err);
+            }
+        }
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(std::mem::take(&mut self.errors)),
         }
     }
 
-    pub fn build(&mut self, tokens: Vec<Token>) -> Result<(), &'static str> {
-        let mut tokens = tokens.iter().peekable();
-        self.statements = Program::get_statements(self, &mut tokens, None)?;
+    /// Checks every GOTO and GOSUB against the labels declared anywhere in the program,
+    /// now that forward references can be resolved too.
+    fn resolve_labels(&mut self) {
+        let undeclared: Vec<CompileError> = self
+            .labels_gotoed
+            .iter()
+            .filter(|(ident, _, _)| !self.labels_declared.contains_key(ident))
+            .map(|(ident, span, keyword)| match is_line_number(ident) {
+                true => CompileError::semantic(
+                    format!("Attempt to {keyword} line {ident}, which does not exist"),
+                    *span,
+                ),
+                false => CompileError::semantic(
+                    format!("Attempt to {keyword} undeclared label '{ident}'"),
+                    *span,
+                ),
+            })
+            .collect();
+        for err in undeclared {
+            self.record_error(err);
+        }
+        self.errors
+            .sort_by_key(|err| err.span().map(|span| span.start));
+
+        let mut unused: Vec<(&String, &Span)> = self
+            .labels_declared
+            .iter()
+            // Every line of a numbered listing has a label; only named ones are worth a warning.
+            .filter(|(ident, _)| !is_line_number(ident))
+            .filter(|(ident, _)| !self.labels_gotoed.iter().any(|(goto, _, _)| goto == *ident))
+            .collect();
+        unused.sort_by_key(|(_, span)| span.start);
+        for (ident, span) in unused {
+            self.warnings.push(Diagnostic::warning(
+                format!("Label '{ident}' is never the target of a GOTO or GOSUB"),
+                *span,
+            ));
+        }
+    }
+
+    /// Declares a jump target, rejecting a second declaration of the same one. `span` is
+    /// that of the statement, and `ident_span` that of the label itself.
+    fn declare_label(
+        &mut self,
+        ident: String,
+        span: Span,
+        ident_span: Span,
+    ) -> Result<Statement, CompileError> {
+        if let Some(first) = self.labels_declared.get(&ident) {
+            let message = match is_line_number(&ident) {
+                true => format!("Line number {ident} is already used on line {}", first.line),
+                false => format!("Label '{ident}' is already declared on line {}", first.line),
+            };
+            return Err(CompileError::semantic(message, ident_span));
+        }
+        self.labels_declared.insert(ident.clone(), ident_span);
+        Ok(Statement::Label {
+            ident,
+            span,
+            ident_span,
+        })
+    }
+
+    /// Parses a FUNCTION definition, from its name to the end of the ENDFUNCTION line,
+    /// into `functions`. The body gets a symbol table and labels of its own.
+    fn function_definition(&mut self, tokens: &mut TokenStream) -> Result<(), CompileError> {
+        let span = tokens.peek_span();
+        let name = tokens.expect_ident("Expected identifier after 'FUNCTION'")?;
+        tokens.expect(
+            &Token::LParen,
+            &format!("Expected '(' after 'FUNCTION {name}'"),
+        )?;
+        let mut params: Vec<String> = Vec::new();
+        if tokens.next_if(|&tok| *tok == Token::RParen).is_none() {
+            loop {
+                let param_span = tokens.peek_span();
+                let param = tokens.expect_ident("Expected parameter name")?;
+                if params.contains(&param) {
+                    return Err(CompileError::semantic(
+                        format!("Parameter '{param}' is already declared"),
+                        param_span,
+                    ));
+                }
+                params.push(param);
+                if tokens.next_if(|&tok| *tok == Token::Comma).is_none() {
+                    tokens.expect(&Token::RParen, "Expected ',' or ')' after parameter")?;
+                    break;
+                }
+            }
+        }
+        tokens.expect(
+            &Token::NewLine,
+            &format!("Expected newline after 'FUNCTION {name}(...)'"),
+        )?;
+        if let Some(first) = self.functions.iter().find(|function| function.name == name) {
+            return Err(CompileError::semantic(
+                format!(
+                    "Function '{name}' is already defined on line {}",
+                    first.span.line
+                ),
+                span,
+            ));
+        }
+
+        let symbols = std::mem::replace(&mut self.symbols, params.iter().cloned().collect());
+        let labels_declared = std::mem::take(&mut self.labels_declared);
+        let labels_gotoed = std::mem::take(&mut self.labels_gotoed);
+        self.in_function = Some(name.clone());
+        let body = self.get_statements(tokens, &[Token::EndFunction]);
+        self.resolve_labels();
+        self.in_function = None;
+        let function_symbols = std::mem::replace(&mut self.symbols, symbols);
+        self.labels_declared = labels_declared;
+        self.labels_gotoed = labels_gotoed;
+
+        let (statements, _) = body?;
+        tokens.expect(&Token::NewLine, "Expected newline after 'ENDFUNCTION'")?;
+        self.functions.push(Function {
+            name,
+            params,
+            statements,
+            symbols: function_symbols,
+            types: HashMap::new(),
+            span,
+        });
         Ok(())
     }
 
+    fn record_error(&mut self, err: CompileError) {
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(err);
+        }
+    }
+
+    /// Parses statements up to and including the first of `sentinels`, which is returned
+    /// alongside them. The last sentinel is the one that closes the block, and the only one
+    /// mentioned if the input runs out first. With no sentinels, parses to the end.
     fn get_statements<'a>(
         &mut self,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-        sentinel: Option<Token>,
-    ) -> Result<Vec<Statement>, &'static str> {
+        tokens: &mut TokenStream<'a>,
+        sentinels: &[Token],
+    ) -> Result<(Vec<Statement>, Option<&'a Token>), CompileError> {
         let mut statements: Vec<Statement> = Vec::new();
-        while let Some(token) = tokens.next() {
-            if sentinel.clone().is_some_and(|s| s == *token) {
-                break;
-            }
-            match token {
-                Token::Let => statements.push(Statement::let_statement(self, tokens)?),
-                Token::Print => statements.push(Statement::print_statement(self, tokens)?),
-                Token::If => statements.push(Statement::if_statement(self, tokens)?),
-                Token::While => statements.push(Statement::while_statement(self, tokens)?),
-                Token::Input => {
-                    statements.push(Statement::ident_statement(self, tokens, Token::Input)?)
-                }
-                Token::Goto => {
-                    statements.push(Statement::ident_statement(self, tokens, Token::Goto)?)
+        while self.errors.len() < MAX_ERRORS {
+            let mut start = tokens.peek_span();
+            let Some(mut token) = tokens.next() else {
+                return match sentinels.last() {
+                    Some(sentinel) => Err(tokens.unexpected(&format!("Expected {sentinel}"))),
+                    None => Ok((statements, None)),
+                };
+            };
+            if let (Dialect::LineNumbers, Token::Int(number)) = (self.dialect, token) {
+                match self.declare_label(number.to_string(), start, start) {
+                    Ok(label) => statements.push(label),
+                    Err(err) => self.record_error(err),
                 }
-                Token::Label => {
-                    statements.push(Statement::ident_statement(self, tokens, Token::Label)?)
+                start = tokens.peek_span();
+                token = match tokens.next() {
+                    Some(token) => token,
+                    None => continue,
+                };
+            }
+            if sentinels.contains(token) {
+                return Ok((statements, Some(token)));
+            }
+            let statement = match token {
+                Token::Let => Statement::let_statement(self, tokens),
+                Token::Print => Statement::print_statement(self, tokens),
+                Token::If => Statement::if_statement(self, tokens),
+                Token::While => Statement::while_statement(self, tokens),
+                Token::For => Statement::for_statement(self, tokens),
+                Token::Input => Statement::ident_statement(self, tokens, Token::Input),
+                Token::Goto => Statement::ident_statement(self, tokens, Token::Goto),
+                Token::Gosub => Statement::ident_statement(self, tokens, Token::Gosub),
+                Token::Return => Statement::return_statement(self, tokens),
+                Token::Label => Statement::ident_statement(self, tokens, Token::Label),
+                // Functions are collected separately rather than being statements.
+                Token::Function if self.in_function.is_none() && sentinels.is_empty() => {
+                    match self.function_definition(tokens) {
+                        Ok(()) => continue,
+                        Err(err) => Err(err),
+                    }
                 }
+                Token::Function => Err(CompileError::syntax(
+                    "FUNCTION can only be defined at the top level of the program",
+                    start,
+                )),
                 Token::NewLine => continue,
-                _ => todo!("statement not implemented"),
+                _ => Err(unexpected_statement(token, start, tokens, sentinels.last())),
+            };
+            match statement {
+                Ok(statement) => statements.push(statement),
+                Err(err) => {
+                    // An error on the opening line of a block means its body can't be
+                    // trusted, so skip ahead to the matching ENDIF/ENDWHILE/NEXT/ENDFUNCTION.
+                    let opens_block = matches!(
+                        token,
+                        Token::If | Token::While | Token::For | Token::Function
+                    ) && err.span().is_some_and(|span| span.line == start.line);
+                    self.record_error(err);
+                    tokens.synchronize(opens_block);
+                }
             }
         }
-        Ok(statements)
+        Ok((statements, None))
     }
 }
 
-#[derive(Debug)]
+/// Labels made from line numbers are the only ones that can start with a digit.
+pub fn is_line_number(ident: &str) -> bool {
+    ident.starts_with(|chr: char| chr.is_ascii_digit())
+}
+
+/// Error for a token that cannot begin a statement, with a hint at what was meant.
+fn unexpected_statement(
+    token: &Token,
+    span: Span,
+    tokens: &TokenStream,
+    sentinel: Option<&Token>,
+) -> CompileError {
+    let err = CompileError::syntax(format!("Unexpected {token} at start of statement"), span);
+    match token {
+        Token::Ident(name) if tokens.peek() == Some(&Token::Assign) => {
+            err.with_help(format!("to assign to '{name}', write 'LET {name} = ...'"))
+        }
+        Token::Ident(name) => match suggest_keyword(name) {
+            Some(keyword) => err.with_help(format!("did you mean '{keyword}'?")),
+            None => err,
+        },
+        Token::EndIf | Token::EndWhile | Token::Next | Token::EndFunction => match sentinel {
+            Some(sentinel) => {
+                err.with_help(format!("the open block must be closed with {sentinel}"))
+            }
+            None => err.with_help(format!("there is no open block for {token} to close")),
+        },
+        Token::Else | Token::ElseIf => match sentinel {
+            // Only the body of an ELSE is closed by ENDIF alone.
+            Some(Token::EndIf) => {
+                err.with_help("an IF block can only have one 'ELSE', and it must come last")
+            }
+            _ => err.with_help(format!("{token} can only be used between 'IF' and 'ENDIF'")),
+        },
+        _ => err,
+    }
+}
+
+/// Finds the keyword closest to `word`, if any is close enough to be a likely typo.
+fn suggest_keyword(word: &str) -> Option<&'static str> {
+    let word = word.to_uppercase();
+    crate::lexer::KEYWORDS
+        .iter()
+        .map(|keyword| (edit_distance(&word, keyword), *keyword))
+        .filter(|(distance, keyword)| *distance <= 2 && *distance < keyword.len() / 2 + 1)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, chr_a) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, chr_b) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(chr_a != *chr_b);
+            row.push(substitute.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn test_suggest_keyword() {
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(suggest_keyword("PRNT"), Some("PRINT"));
+    assert_eq!(suggest_keyword("ENDWHIL"), Some("ENDWHILE"));
+    assert_eq!(suggest_keyword("print"), Some("PRINT"));
+    assert_eq!(suggest_keyword("LT"), Some("LET"));
+    assert_eq!(suggest_keyword("banana"), None);
+    assert_eq!(suggest_keyword("x"), None);
+}
+
+/// A statement of the program. Every one has the `span` of the keyword it starts with, or
+/// of its line number for the labels of a numbered listing, and those naming a variable
+/// or label have the `ident_span` of that name too.
+#[derive(Debug, Clone)]
 pub enum Statement {
     Let {
         ident: String,
         expression: Expression,
+        span: Span,
+        ident_span: Span,
+    },
+    /// Prints `items` in order, then a newline unless the statement ended with a separator.
+    Print {
+        items: Vec<PrintItem>,
+        newline: bool,
+        span: Span,
     },
-    Print(PrintMessage),
+    /// Runs the statements of the first branch whose condition holds, or the ELSE
+    /// statements if none does.
     If {
-        comparison: Comparison,
-        statements: Vec<Statement>,
+        branches: Vec<Branch>,
+        else_statements: Option<Vec<Statement>>,
+        span: Span,
     },
     While {
-        comparison: Comparison,
+        condition: Condition,
         statements: Vec<Statement>,
+        span: Span,
+    },
+    /// Counts `ident` from `start` to `end` in steps of `step`, or of 1 without a STEP.
+    /// `end` and `step` are evaluated once, before the first pass.
+    For {
+        ident: String,
+        start: Box<Expression>,
+        end: Box<Expression>,
+        step: Option<Box<Expression>>,
+        statements: Vec<Statement>,
+        span: Span,
+        ident_span: Span,
     },
     Label {
         ident: String,
+        span: Span,
+        ident_span: Span,
     },
     Goto {
         ident: String,
+        span: Span,
+        ident_span: Span,
+    },
+    /// Jumps to `ident`, to come back to the next statement at the following RETURN.
+    Gosub {
+        ident: String,
+        span: Span,
+        ident_span: Span,
+    },
+    /// Comes back from a GOSUB or, with a value, from a FUNCTION.
+    Return {
+        value: Option<Expression>,
+        span: Span,
     },
     Input {
         ident: String,
+        span: Span,
+        ident_span: Span,
     },
 }
 
-#[derive(Debug, PartialEq)]
-pub enum PrintMessage {
+/// The IF or an ELSEIF of an IF block.
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub condition: Condition,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrintItem {
     Expression(Expression),
-    StrLit(String),
+    /// A `,` between items, which moves the output on to the next print zone.
+    Tab,
 }
 
+/// Width of the print zones that `,` tabs between, as in the classic BASICs.
+pub const PRINT_ZONE_WIDTH: usize = 14;
+
+/// How many GOSUBs may be waiting for their RETURN before the program stops with an error.
+pub const GOSUB_DEPTH: usize = 256;
+
+/// How many function calls may be in progress at once before the program stops with an
+/// error, rather than overflowing the stack.
+pub const CALL_DEPTH: usize = 256;
+
 impl Statement {
     fn ident_statement<'a>(
         program: &mut Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
+        tokens: &mut TokenStream<'a>,
         statement_type: Token,
-    ) -> Result<Statement, &'static str> {
-        let ident = match tokens.next().ok_or("Expected identifier, got EOF") {
-            Ok(Token::Ident(name)) => name.clone(),
-            _ => return Err("Expected identifier"),
+    ) -> Result<Statement, CompileError> {
+        let keyword_span = tokens.prev_span();
+        let ident_span = tokens.peek_span();
+        let jumps = matches!(statement_type, Token::Goto | Token::Gosub);
+        let ident = match tokens.peek() {
+            Some(Token::Int(number)) if jumps && program.dialect == Dialect::LineNumbers => {
+                tokens.next();
+                number.to_string()
+            }
+            _ => tokens.expect_ident(&format!("Expected identifier after {statement_type}"))?,
         };
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline");
-        }
+        tokens.expect(
+            &Token::NewLine,
+            &format!("Expected newline after {statement_type} statement"),
+        )?;
         match statement_type {
-            Token::Goto => match program.labels_declared.contains(&ident) {
-                true => {
-                    program.labels_gotoed.insert(ident.clone());
-                    Ok(Statement::Goto { ident })
-                }
-                false => Err("Attempt to GOTO undeclared label: {ident}"),
-            },
-            Token::Label => {
-                program.labels_declared.insert(ident.clone());
-                Ok(Statement::Label { ident })
+            Token::Goto => {
+                // Labels may be declared further down, so they are checked after parsing.
+                program
+                    .labels_gotoed
+                    .push((ident.clone(), ident_span, "GOTO"));
+                Ok(Statement::Goto {
+                    ident,
+                    span: keyword_span,
+                    ident_span,
+                })
             }
+            // A FUNCTION's labels are its own, so there would be no way back to the caller.
+            Token::Gosub if program.in_function.is_some() => Err(CompileError::semantic(
+                "GOSUB cannot be used inside a FUNCTION",
+                keyword_span,
+            )),
+            Token::Gosub => {
+                program
+                    .labels_gotoed
+                    .push((ident.clone(), ident_span, "GOSUB"));
+                Ok(Statement::Gosub {
+                    ident,
+                    span: keyword_span,
+                    ident_span,
+                })
+            }
+            Token::Label => program.declare_label(ident, keyword_span, ident_span),
             Token::Input => {
                 program.symbols.insert(ident.clone());
-                Ok(Statement::Input { ident })
+                Ok(Statement::Input {
+                    ident,
+                    span: keyword_span,
+                    ident_span,
+                })
             }
-            _ => Err("Invalid statement type!"),
+            _ => Err(CompileError::syntax(
+                format!("Invalid statement type {statement_type}"),
+                ident_span,
+            )),
         }
     }
 
-    fn let_statement<'a>(
+    fn return_statement<'a>(
         program: &mut Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Statement, &'static str> {
-        let ident = match tokens.next().ok_or("Expected identifier, got EOF") {
-            Ok(Token::Ident(name)) => name.clone(),
-            _ => return Err("Expected identifier"),
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        let span = tokens.prev_span();
+        let value = match (&program.in_function, tokens.peek()) {
+            (Some(name), Some(Token::NewLine) | None) => {
+                return Err(tokens.unexpected(&format!(
+                    "Expected the value for FUNCTION '{name}' to RETURN"
+                )))
+            }
+            (Some(_), _) => Some(Expression::build(program, tokens)?),
+            (None, _) => None,
         };
+        tokens
+            .expect(&Token::NewLine, "Expected newline after 'RETURN'")
+            .map_err(|err| match value {
+                Some(_) => err,
+                None => err.with_help("only a FUNCTION can RETURN a value"),
+            })?;
+        Ok(Statement::Return { value, span })
+    }
 
-        match tokens.next().ok_or("Expected '=', got EOF") {
-            Ok(Token::Assign) => {}
-            _ => return Err("Expected '='"),
-        }
+    fn let_statement<'a>(
+        program: &mut Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        let span = tokens.prev_span();
+        let ident_span = tokens.peek_span();
+        let ident = tokens.expect_ident("Expected identifier after 'LET'")?;
+        tokens.expect(&Token::Assign, &format!("Expected '=' after 'LET {ident}'"))?;
 
         let expression = Expression::build(program, tokens)?;
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline after 'LET' statement");
-        }
+        tokens.expect(&Token::NewLine, "Expected newline after 'LET' statement")?;
         program.symbols.insert(ident.clone());
 
-        Ok(Statement::Let { ident, expression })
+        Ok(Statement::Let {
+            ident,
+            expression,
+            span,
+            ident_span,
+        })
     }
 
     fn print_statement<'a>(
         program: &mut Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Statement, &'static str> {
-        let message = match tokens.peek().ok_or("Expected string literal or expression") {
-            Ok(Token::StrLit(msg)) => PrintMessage::StrLit(msg.clone()),
-            _ => PrintMessage::Expression(Expression::build(program, tokens)?),
-        };
-
-        // TODO: cleaner implementation here. The Expression::build function
-        // consumes the newline, while the StrLit does not.
-        match message {
-            PrintMessage::StrLit(_) => {
-                tokens.next();
-                if tokens.next() != Some(&Token::NewLine) {
-                    return Err("Expected newline after 'PRINT' statement");
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        let span = tokens.prev_span();
+        let mut items = Vec::new();
+        let mut newline = true;
+        loop {
+            match tokens.peek() {
+                Some(Token::NewLine) | None => break,
+                Some(Token::Comma) => {
+                    tokens.next();
+                    items.push(PrintItem::Tab);
+                    newline = false;
+                }
+                Some(Token::Semicolon) => {
+                    tokens.next();
+                    newline = false;
+                }
+                _ => {
+                    items.push(PrintItem::Expression(Expression::build(program, tokens)?));
+                    newline = true;
+                    if !matches!(
+                        tokens.peek(),
+                        Some(Token::NewLine | Token::Comma | Token::Semicolon) | None
+                    ) {
+                        return Err(
+                            tokens.unexpected("Expected ',', ';' or newline after PRINT item")
+                        );
+                    }
                 }
             }
-            _ => {}
-        };
-        Ok(Statement::Print(message))
+        }
+        tokens.expect(&Token::NewLine, "Expected newline after 'PRINT' statement")?;
+        Ok(Statement::Print {
+            items,
+            newline,
+            span,
+        })
     }
 
     fn if_statement<'a>(
         program: &mut Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Statement, &'static str> {
-        let comparison = Comparison::build(program, tokens)?;
-        if tokens.next() != Some(&Token::Then) {
-            return Err("Expected 'THEN' after 'IF' comparison");
-        }
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline after 'THEN'");
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        const BRANCH_ENDS: &[Token] = &[Token::ElseIf, Token::Else, Token::EndIf];
+        let span = tokens.prev_span();
+        let condition = Statement::branch_header(program, tokens, &Token::If)?;
+        let (statements, mut closed_by) = program.get_statements(tokens, BRANCH_ENDS)?;
+        let mut branches = vec![Branch {
+            condition,
+            statements,
+        }];
+        // An error on an ELSEIF or ELSE line means the rest of the block can't be trusted,
+        // so skip ahead to the ENDIF.
+        while closed_by == Some(&Token::ElseIf) {
+            let condition = Statement::branch_header(program, tokens, &Token::ElseIf)
+                .inspect_err(|_| tokens.synchronize(true))?;
+            let statements;
+            (statements, closed_by) = program.get_statements(tokens, BRANCH_ENDS)?;
+            branches.push(Branch {
+                condition,
+                statements,
+            });
         }
-        let statements = program.get_statements(tokens, Some(Token::EndIf))?;
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline after 'ENDIF'");
+        let mut else_statements = None;
+        if closed_by == Some(&Token::Else) {
+            tokens
+                .expect(&Token::NewLine, "Expected newline after 'ELSE'")
+                .inspect_err(|_| tokens.synchronize(true))?;
+            let (statements, _) = program.get_statements(tokens, &[Token::EndIf])?;
+            else_statements = Some(statements);
         }
+        tokens.expect(&Token::NewLine, "Expected newline after 'ENDIF'")?;
         Ok(Statement::If {
-            comparison,
-            statements,
+            branches,
+            else_statements,
+            span,
         })
     }
 
+    /// The rest of an `IF` or `ELSEIF` line: a condition, `THEN` and a newline.
+    fn branch_header<'a>(
+        program: &mut Program,
+        tokens: &mut TokenStream<'a>,
+        keyword: &Token,
+    ) -> Result<Condition, CompileError> {
+        let condition = Condition::build(program, tokens)?;
+        tokens.expect(
+            &Token::Then,
+            &format!("Expected 'THEN' after {keyword} condition"),
+        )?;
+        tokens.expect(&Token::NewLine, "Expected newline after 'THEN'")?;
+        Ok(condition)
+    }
+
     fn while_statement<'a>(
         program: &mut Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Statement, &'static str> {
-        let comparison = Comparison::build(program, tokens)?;
-        if tokens.next() != Some(&Token::Repeat) {
-            return Err("Expected 'REPEAT' after 'WHILE' comparison");
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        let span = tokens.prev_span();
+        let condition = Condition::build(program, tokens)?;
+        tokens.expect(&Token::Repeat, "Expected 'REPEAT' after 'WHILE' condition")?;
+        tokens.expect(&Token::NewLine, "Expected newline after 'REPEAT'")?;
+        let (statements, _) = program.get_statements(tokens, &[Token::EndWhile])?;
+        tokens.expect(&Token::NewLine, "Expected newline after 'ENDWHILE'")?;
+        Ok(Statement::While {
+            condition,
+            statements,
+            span,
+        })
+    }
+
+    fn for_statement<'a>(
+        program: &mut Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Statement, CompileError> {
+        let span = tokens.prev_span();
+        let ident_span = tokens.peek_span();
+        let ident = tokens.expect_ident("Expected identifier after 'FOR'")?;
+        tokens.expect(&Token::Assign, &format!("Expected '=' after 'FOR {ident}'"))?;
+        let start = Box::new(Expression::build(program, tokens)?);
+        // The variable is set before the limit and step are worked out, so they may use it.
+        program.symbols.insert(ident.clone());
+        tokens.expect(&Token::To, "Expected 'TO' after 'FOR' start value")?;
+        let end = Box::new(Expression::build(program, tokens)?);
+        let step = match tokens.next_if(|&tok| *tok == Token::Step) {
+            Some(_) => Some(Box::new(Expression::build(program, tokens)?)),
+            None => None,
+        };
+        tokens.expect(&Token::NewLine, "Expected newline after 'FOR' statement")?;
+        if types::is_string_ident(&ident) {
+            return Err(CompileError::semantic(
+                format!("Cannot count with string variable '{ident}'"),
+                ident_span,
+            ));
         }
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline after 'REPEAT'");
+        if program.open_loops.contains(&ident) {
+            return Err(CompileError::semantic(
+                format!("'{ident}' is already counted by an enclosing FOR loop"),
+                ident_span,
+            ));
         }
-        let statements = program.get_statements(tokens, Some(Token::EndWhile))?;
-        if tokens.next() != Some(&Token::NewLine) {
-            return Err("Expected newline after 'ENDWHILE'");
+
+        program.open_loops.push(ident.clone());
+        let body = program.get_statements(tokens, &[Token::Next]);
+        program.open_loops.pop();
+        let (statements, _) = body?;
+        let next_span = tokens.peek_span();
+        let next = tokens.expect_ident("Expected identifier after 'NEXT'")?;
+        if next != ident {
+            return Err(CompileError::syntax(
+                format!(
+                    "Expected 'NEXT {ident}' to close the FOR loop on line {}, found 'NEXT {next}'",
+                    ident_span.line
+                ),
+                next_span,
+            ));
         }
-        Ok(Statement::While {
-            comparison,
+        tokens.expect(&Token::NewLine, "Expected newline after 'NEXT' statement")?;
+        Ok(Statement::For {
+            ident,
+            start,
+            end,
+            step,
             statements,
+            span,
+            ident_span,
         })
     }
 }
 
 trait Build {
-    fn build<'a>(
-        program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Self, &'static str>
+    fn build<'a>(program: &Program, tokens: &mut TokenStream<'a>) -> Result<Self, CompileError>
     where
         Self: Sized;
 }
 
-#[derive(Debug)]
+/// The test of an IF or WHILE: comparisons combined with `NOT`, `AND` and `OR`, which bind
+/// in that order from tightest to loosest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Or(Box<Condition>, Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Comparison(Box<Comparison>),
+}
+
+impl Build for Condition {
+    fn build<'a>(
+        program: &Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Condition, CompileError> {
+        let mut condition = Condition::build_and(program, tokens)?;
+        while tokens.next_if(|&tok| *tok == Token::Or).is_some() {
+            let right = Condition::build_and(program, tokens)?;
+            condition = Condition::Or(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+}
+
+impl Condition {
+    fn build_and<'a>(
+        program: &Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Condition, CompileError> {
+        let mut condition = Condition::build_not(program, tokens)?;
+        while tokens.next_if(|&tok| *tok == Token::And).is_some() {
+            let right = Condition::build_not(program, tokens)?;
+            condition = Condition::And(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+
+    fn build_not<'a>(
+        program: &Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Condition, CompileError> {
+        match tokens.peek() {
+            Some(Token::Not) => {
+                tokens.next();
+                Ok(Condition::Not(Box::new(Condition::build_not(
+                    program, tokens,
+                )?)))
+            }
+            // A '(' may open either a grouped condition, as in `(a > 1 OR b > 1) AND c > 1`,
+            // or the first operand of a comparison, as in `(a + 1) * 2 > b`. Try the former
+            // first and fall back to the latter.
+            Some(Token::LParen) => {
+                let mut group = tokens.clone();
+                let grouped = Condition::build_group(program, &mut group);
+                if grouped.is_ok() {
+                    *tokens = group;
+                    return grouped;
+                }
+                let comparison = Comparison::build(program, tokens)
+                    .map(|comparison| Condition::Comparison(Box::new(comparison)));
+                match (grouped, comparison) {
+                    // Both failed, so report whichever error got further into the input.
+                    (Err(grouped), Err(comparison))
+                        if grouped.span().map(|span| span.start)
+                            > comparison.span().map(|span| span.start) =>
+                    {
+                        Err(grouped)
+                    }
+                    (_, comparison) => comparison,
+                }
+            }
+            _ => Ok(Condition::Comparison(Box::new(Comparison::build(
+                program, tokens,
+            )?))),
+        }
+    }
+
+    fn build_group<'a>(
+        program: &Program,
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Condition, CompileError> {
+        let span = tokens.peek_span();
+        tokens.next();
+        let condition = Condition::build(program, tokens)?;
+        tokens.expect(
+            &Token::RParen,
+            &format!("Expected ')' to close '(' at {}:{}", span.line, span.col),
+        )?;
+        Ok(condition)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Comparison {
     pub left: Expression,
     pub operator: Token,
@@ -232,18 +1016,21 @@ pub struct Comparison {
 impl Build for Comparison {
     fn build<'a>(
         program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Comparison, &'static str> {
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Comparison, CompileError> {
         let left = Expression::build(program, tokens)?;
-        let operator = match tokens.next().ok_or("Expected operator") {
-            Ok(Token::Equals) => Token::Equals,
-            Ok(Token::NotEquals) => Token::NotEquals,
-            Ok(Token::Gt) => Token::Gt,
-            Ok(Token::Gte) => Token::Gte,
-            Ok(Token::Lt) => Token::Lt,
-            Ok(Token::Lte) => Token::Lte,
-            _ => return Err("Expected operator!"),
+        let operator = match tokens.peek() {
+            Some(
+                op @ (Token::Equals
+                | Token::NotEquals
+                | Token::Gt
+                | Token::Gte
+                | Token::Lt
+                | Token::Lte),
+            ) => op.clone(),
+            _ => return Err(tokens.unexpected("Expected comparison operator")),
         };
+        tokens.next();
         let right = Expression::build(program, tokens)?;
         Ok(Comparison {
             left,
@@ -253,106 +1040,797 @@ impl Build for Comparison {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A sum of terms: `first_term` followed by any number of `+ term` / `- term` components.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Expression {
     pub first_term: Term,
-    pub other_terms: Vec<Term>,
+    pub other_terms: Vec<ExprComp>,
+    pub span: Span,
 }
 
 impl Build for Expression {
     fn build<'a>(
         program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Expression, &'static str> {
+        tokens: &mut TokenStream<'a>,
+    ) -> Result<Expression, CompileError> {
+        let start = tokens.peek_span();
         let first_term = Term::build(program, tokens)?;
-        let mut other_terms: Vec<Term> = Vec::new();
-        while let Ok(term) = Term::build(program, tokens) {
-            other_terms.push(term);
+        let mut other_terms: Vec<ExprComp> = Vec::new();
+        while let Some(operator) =
+            tokens.next_if(|&tok| (*tok == Token::Add) | (*tok == Token::Sub))
+        {
+            other_terms.push(ExprComp {
+                operator: operator.clone(),
+                term: Term::build(program, tokens)?,
+            });
+        }
+        // Two operands next to each other, as in `a b`, are almost certainly a missing operator.
+        if let Some(
+            Token::Ident(_) | Token::Int(_) | Token::Float(_) | Token::StrLit(_) | Token::LParen,
+        ) = tokens.peek()
+        {
+            return Err(tokens.unexpected("Expected an operator between terms"));
         }
         Ok(Expression {
             first_term,
             other_terms,
+            span: start.to(tokens.prev_span()),
         })
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprComp {
+    pub operator: Token,
+    pub term: Term,
+}
+
+/// A product of unary expressions: `unary` followed by any number of `* unary` / `/ unary`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Term {
     pub unary: Unary,
     pub components: Vec<TermComp>,
 }
 impl Build for Term {
-    fn build<'a>(
-        program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Term, &'static str> {
+    fn build<'a>(program: &Program, tokens: &mut TokenStream<'a>) -> Result<Term, CompileError> {
         let unary = Unary::build(program, tokens)?;
         let mut components: Vec<TermComp> = Vec::new();
-        while let Some(component) = TermComp::build(program, tokens) {
-            components.push(component);
+        while let Some(operator) =
+            tokens.next_if(|&tok| (*tok == Token::Mul) | (*tok == Token::Div))
+        {
+            components.push(TermComp {
+                operator: operator.clone(),
+                unary: Unary::build(program, tokens)?,
+            });
         }
         Ok(Term { unary, components })
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TermComp {
     pub operator: Token,
     pub unary: Unary,
 }
-impl TermComp {
-    fn build<'a>(
-        program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Option<TermComp> {
-        let operator = match tokens.next_if(|&tok| (*tok == Token::Mul) | (*tok == Token::Div)) {
-            Some(op) => op,
-            None => return None,
-        };
-        let unary = match Unary::build(program, tokens) {
-            Ok(un) => un,
-            _ => return None,
-        };
 
-        Some(TermComp {
-            operator: operator.clone(),
-            unary,
-        })
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unary {
+    /// Unary minus, e.g. `-a` or the `-b` in `a - -b`.
+    Negate(Box<Unary>),
+    Primary(Primary),
+}
+
+impl Build for Unary {
+    fn build<'a>(program: &Program, tokens: &mut TokenStream<'a>) -> Result<Unary, CompileError> {
+        match tokens.next_if(|&tok| (*tok == Token::Add) | (*tok == Token::Sub)) {
+            Some(Token::Sub) => Ok(Unary::Negate(Box::new(Unary::build(program, tokens)?))),
+            // Unary plus doesn't change the value, so it isn't kept in the tree.
+            Some(_) => Unary::build(program, tokens),
+            None => Ok(Unary::Primary(Primary::build(program, tokens)?)),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Unary {
-    pub operator: Option<Token>,
-    pub primary: Primary,
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primary {
+    Float(f64),
+    Int(i32),
+    /// A string literal used as a value, as in `LET a$ = "x"`.
+    Str(String),
+    Ident(String),
+    /// A parenthesized sub-expression.
+    Group(Box<Expression>),
+    /// A call to a FUNCTION, written with the `(` right after the name, as in `f(x)`.
+    Call {
+        name: String,
+        args: Vec<Expression>,
+        span: Span,
+    },
 }
 
-impl Build for Unary {
-    fn build<'a>(
-        program: &Program,
-        tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
-    ) -> Result<Unary, &'static str> {
-        let operator = tokens.next_if(|&tok| (*tok == Token::Add) | (*tok == Token::Sub));
-        let primary = match tokens.peek().ok_or("Expected Primary Token") {
-            Ok(Token::Float(val)) => Primary::Float(*val),
-            Ok(Token::Int(val)) => Primary::Int(*val),
-            Ok(Token::Ident(name)) => match program.symbols.contains(name) {
+impl Build for Primary {
+    fn build<'a>(program: &Program, tokens: &mut TokenStream<'a>) -> Result<Primary, CompileError> {
+        let span = tokens.peek_span();
+        let primary = match tokens.peek() {
+            Some(Token::Float(val)) => Primary::Float(*val),
+            Some(Token::Int(val)) => Primary::Int(*val),
+            Some(Token::StrLit(text)) => Primary::Str(text.clone()),
+            // Functions may be defined further down, so calls are checked after parsing.
+            Some(Token::Ident(name)) if tokens.opens_call(&program.symbols) => {
+                tokens.next();
+                tokens.next();
+                let mut args = Vec::new();
+                if tokens.next_if(|&tok| *tok == Token::RParen).is_none() {
+                    loop {
+                        args.push(Expression::build(program, tokens)?);
+                        if tokens.next_if(|&tok| *tok == Token::Comma).is_none() {
+                            tokens.expect(
+                                &Token::RParen,
+                                &format!("Expected ',' or ')' in call to '{name}'"),
+                            )?;
+                            break;
+                        }
+                    }
+                }
+                return Ok(Primary::Call {
+                    name: name.clone(),
+                    args,
+                    span: span.to(tokens.prev_span()),
+                });
+            }
+            Some(Token::Ident(name)) => match program.symbols.contains(name) {
                 true => Primary::Ident(name.clone()),
-                false => return Err("Undeclared identifier!"),
+                false => {
+                    return Err(CompileError::semantic(
+                        format!("Undeclared identifier '{name}'"),
+                        span,
+                    ))
+                }
             },
-            _ => return Err("expected primary token"),
+            Some(Token::LParen) => {
+                tokens.next();
+                let expression = Expression::build(program, tokens)?;
+                tokens.expect(
+                    &Token::RParen,
+                    &format!("Expected ')' to close '(' at {}:{}", span.line, span.col),
+                )?;
+                return Ok(Primary::Group(Box::new(expression)));
+            }
+            _ => return Err(tokens.unexpected("Expected number, string, identifier or '('")),
         };
         tokens.next();
-        Ok(Unary {
-            operator: operator.cloned(),
-            primary,
+        Ok(primary)
+    }
+}
+
+#[test]
+fn test_error_span() {
+    let diagnostics = build_source("LET a = 1\nLET b = 2 THEN\n").unwrap_err();
+    assert_eq!(
+        diagnostics.errors,
+        vec![CompileError::syntax(
+            "Expected newline after 'LET' statement, found 'THEN'",
+            Span::new(20, 24, 2, 11)
+        )]
+    );
+}
+
+#[test]
+fn test_goto_undeclared_label() {
+    let diagnostics = build_source("GOTO main\n").unwrap_err();
+    assert_eq!(
+        diagnostics.errors,
+        vec![CompileError::semantic(
+            "Attempt to GOTO undeclared label 'main'",
+            Span::new(5, 9, 1, 6)
+        )]
+    );
+}
+
+#[test]
+fn test_error_recovery() {
+    let source = "\
+LET a = 1
+PRINT a
+IF a > THEN
+    LET b = 1 +
+    PRINT zzz
+ENDIF
+WHILE a > 0 REPEAT
+    PRINT nope
+    LET a = a - 1
+ENDWHILE
+LET = 4
+LET c = 1 2 3
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let lines: Vec<u32> = errors.iter().map(|err| err.span().unwrap().line).collect();
+    assert_eq!(lines, vec![3, 8, 11, 12]);
+}
+
+#[test]
+fn test_missing_endif() {
+    let errors = build_source("LET a = 1\nIF a > 0 THEN\nPRINT a\n")
+        .unwrap_err()
+        .errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message(), "Expected 'ENDIF', found end of file");
+}
+
+#[test]
+fn test_error_limit() {
+    let source = "PRINT nope\n".repeat(MAX_ERRORS + 5);
+    assert_eq!(build_source(&source).unwrap_err().errors.len(), MAX_ERRORS);
+}
+
+#[test]
+fn test_forward_goto() {
+    let program = build_source("GOTO end\nPRINT \"skipped\"\nLABEL end\n").unwrap();
+    assert!(program.warnings.is_empty());
+}
+
+#[test]
+fn test_label_checks() {
+    let source = "LABEL top\nLABEL unused\nLABEL top\nGOTO top\n";
+    let diagnostics = build_source(source).unwrap_err();
+    assert_eq!(
+        diagnostics.errors,
+        vec![CompileError::semantic(
+            "Label 'top' is already declared on line 1",
+            Span::new(29, 32, 3, 7)
+        )]
+    );
+    assert_eq!(
+        diagnostics.warnings,
+        vec![Diagnostic::warning(
+            "Label 'unused' is never the target of a GOTO or GOSUB",
+            Span::new(16, 22, 2, 7)
+        )]
+    );
+}
+
+#[test]
+fn test_unexpected_statement() {
+    let source = "LET a = 1\nPRNT a\na = 2\nENDIF\n";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<(&str, Option<&str>)> = errors
+        .iter()
+        .map(|err| (err.message(), err.help()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (
+                "Unexpected identifier 'PRNT' at start of statement",
+                Some("did you mean 'PRINT'?")
+            ),
+            (
+                "Unexpected identifier 'a' at start of statement",
+                Some("to assign to 'a', write 'LET a = ...'")
+            ),
+            (
+                "Unexpected 'ENDIF' at start of statement",
+                Some("there is no open block for 'ENDIF' to close")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_groups() {
+    let program = build_source("LET a = 1\nLET b = ((a + 2) * (3))\n").unwrap();
+    let Statement::Let { expression, .. } = &program.statements[1] else {
+        panic!("expected LET statement");
+    };
+    let Unary::Primary(Primary::Group(outer)) = &expression.first_term.unary else {
+        panic!("expected group");
+    };
+    assert!(matches!(
+        outer.first_term.unary,
+        Unary::Primary(Primary::Group(ref inner)) if inner.other_terms.len() == 1
+    ));
+    assert!(matches!(
+        outer.first_term.components[0].unary,
+        Unary::Primary(Primary::Group(_))
+    ));
+}
+
+#[test]
+fn test_unbalanced_groups() {
+    let errors = build_source("LET a = (1 * (2 + 3)\nLET b = 1)\n")
+        .unwrap_err()
+        .errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Expected ')' to close '(' at 1:9, found newline",
+            "Expected newline after 'LET' statement, found ')'",
+        ]
+    );
+}
+
+#[test]
+fn test_binary_operators() {
+    let program = build_source("LET a = 1\nLET b = a - -a * 2 + +3\n").unwrap();
+    let Statement::Let { expression, .. } = &program.statements[1] else {
+        panic!("expected LET statement");
+    };
+    let ident = |name: &str| Unary::Primary(Primary::Ident(name.to_string()));
+    assert_eq!(
+        *expression,
+        Expression {
+            first_term: Term {
+                unary: ident("a"),
+                components: vec![],
+            },
+            other_terms: vec![
+                ExprComp {
+                    operator: Token::Sub,
+                    term: Term {
+                        unary: Unary::Negate(Box::new(ident("a"))),
+                        components: vec![TermComp {
+                            operator: Token::Mul,
+                            unary: Unary::Primary(Primary::Int(2)),
+                        }],
+                    },
+                },
+                ExprComp {
+                    operator: Token::Add,
+                    term: Term {
+                        unary: Unary::Primary(Primary::Int(3)),
+                        components: vec![],
+                    },
+                },
+            ],
+            span: Span::new(18, 33, 2, 9),
+        }
+    );
+}
+
+#[test]
+fn test_malformed_expressions() {
+    let source = "LET a = 1\nLET b = a a\nLET c = a *\nLET d = a + * 2\nPRINT a (a)\n";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Expected an operator between terms, found identifier 'a'",
+            "Expected number, string, identifier or '(', found newline",
+            "Expected number, string, identifier or '(', found '*'",
+            "Expected an operator between terms, found '('",
+        ]
+    );
+}
+
+#[test]
+fn test_line_numbers() {
+    let source = "10 LET a = 1\n20 IF a < 3 THEN\n30 LET a = a + 1\n40 GOTO 20\n50 ENDIF\n";
+    let program = build_dialect(source, Dialect::LineNumbers).unwrap();
+    assert!(program.warnings.is_empty());
+    let Statement::If { branches, .. } = &program.statements[3] else {
+        panic!("expected IF statement");
+    };
+    let statements = &branches[0].statements;
+    assert_eq!(statements.len(), 5);
+    assert!(matches!(&statements[4], Statement::Label { ident, .. } if ident == "50"));
+}
+
+#[test]
+fn test_line_number_errors() {
+    let source = "10 PRINT \"a\"\n10 PRINT \"b\"\n20 GOTO 30\n";
+    let errors = build_dialect(source, Dialect::LineNumbers)
+        .unwrap_err()
+        .errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Line number 10 is already used on line 1",
+            "Attempt to GOTO line 30, which does not exist",
+        ]
+    );
+
+    // Without the dialect, a leading number is not a statement.
+    let errors = build_source("10 PRINT \"a\"\n").unwrap_err().errors;
+    assert_eq!(
+        errors[0].message(),
+        "Unexpected number 10 at start of statement"
+    );
+}
+
+#[test]
+fn test_print_items() {
+    let program = build_source("LET a = 1\nPRINT \"a\", a; a;\nPRINT\nPRINT ,\n").unwrap();
+    let shapes: Vec<(Vec<bool>, bool)> = program.statements[1..]
+        .iter()
+        .map(|statement| match statement {
+            Statement::Print { items, newline, .. } => (
+                items
+                    .iter()
+                    .map(|item| matches!(item, PrintItem::Tab))
+                    .collect(),
+                *newline,
+            ),
+            _ => panic!("expected PRINT statement"),
         })
+        .collect();
+    assert_eq!(
+        shapes,
+        vec![
+            (vec![false, true, false, false], false),
+            (vec![], true),
+            (vec![true], false),
+        ]
+    );
+
+    let errors = build_source("LET a = 1\nPRINT a a\nPRINT a)\n")
+        .unwrap_err()
+        .errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Expected an operator between terms, found identifier 'a'",
+            "Expected ',', ';' or newline after PRINT item, found ')'",
+        ]
+    );
+}
+
+#[test]
+fn test_conditions() {
+    /// Writes out the shape of a condition, fully parenthesized.
+    fn shape(condition: &Condition) -> String {
+        match condition {
+            Condition::Or(left, right) => format!("({} OR {})", shape(left), shape(right)),
+            Condition::And(left, right) => format!("({} AND {})", shape(left), shape(right)),
+            Condition::Not(condition) => format!("NOT {}", shape(condition)),
+            Condition::Comparison(comparison) => comparison.operator.to_string(),
+        }
     }
+    let source = "\
+LET a = 1
+IF a > 1 OR a < 1 AND NOT a == 1 THEN
+ENDIF
+WHILE (a + 1) * 2 >= a AND (a != 1 OR NOT (a <= 1)) REPEAT
+ENDWHILE
+";
+    let program = build_source(source).unwrap();
+    let shapes: Vec<String> = program.statements[1..]
+        .iter()
+        .map(|statement| match statement {
+            Statement::If { branches, .. } => shape(&branches[0].condition),
+            Statement::While { condition, .. } => shape(condition),
+            _ => panic!("expected IF or WHILE statement"),
+        })
+        .collect();
+    assert_eq!(
+        shapes,
+        vec![
+            "('>' OR ('<' AND NOT '=='))",
+            "('>=' AND ('!=' OR NOT '<='))",
+        ]
+    );
+
+    let source = "\
+LET a = 1
+IF (a > 1 AND a) THEN
+ENDIF
+IF a > 1 AND THEN
+ENDIF
+IF (a > 1 THEN
+ENDIF
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Expected comparison operator, found ')'",
+            "Expected number, string, identifier or '(', found 'THEN'",
+            "Expected ')' to close '(' at 6:4, found 'THEN'",
+        ]
+    );
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Primary {
-    Float(f32),
-    Int(i32),
-    Ident(String),
+#[test]
+fn test_else_branches() {
+    let source = "\
+LET a = 1
+IF a > 1 THEN
+    PRINT 1
+ELSEIF a > 0 THEN
+ELSEIF a == 0 THEN
+    PRINT 2
+    PRINT 3
+ELSE
+    PRINT 4
+ENDIF
+IF a > 1 THEN
+ENDIF
+";
+    let program = build_source(source).unwrap();
+    let shapes: Vec<(Vec<usize>, Option<usize>)> = program.statements[1..]
+        .iter()
+        .map(|statement| match statement {
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => (
+                branches
+                    .iter()
+                    .map(|branch| branch.statements.len())
+                    .collect(),
+                else_statements.as_ref().map(Vec::len),
+            ),
+            _ => panic!("expected IF statement"),
+        })
+        .collect();
+    assert_eq!(shapes, vec![(vec![1, 0, 2], Some(1)), (vec![0], None)]);
+
+    let source = "\
+LET a = 1
+ELSE
+IF a > 1 THEN
+ELSE
+ELSEIF a > 0 THEN
+ENDIF
+IF a > 1 THEN
+ELSEIF a THEN
+    PRINT nope
+ELSE
+ENDIF
+PRINT a b
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<(&str, Option<&str>)> = errors
+        .iter()
+        .map(|err| (err.message(), err.help()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (
+                "Unexpected 'ELSE' at start of statement",
+                Some("'ELSE' can only be used between 'IF' and 'ENDIF'")
+            ),
+            (
+                "Unexpected 'ELSEIF' at start of statement",
+                Some("an IF block can only have one 'ELSE', and it must come last")
+            ),
+            ("Expected comparison operator, found 'THEN'", None),
+            (
+                "Expected an operator between terms, found identifier 'b'",
+                None
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_for_loops() {
+    let source = "\
+FOR i = 1 TO 10 STEP 2
+    FOR j = i TO 1
+        PRINT j
+    NEXT j
+NEXT i
+";
+    let program = build_source(source).unwrap();
+    let Statement::For {
+        ident,
+        step,
+        statements,
+        ..
+    } = &program.statements[0]
+    else {
+        panic!("expected FOR statement");
+    };
+    assert_eq!(ident, "i");
+    assert!(step.is_some());
+    assert!(matches!(&statements[0], Statement::For { ident, step: None, .. } if ident == "j"));
+
+    let source = "\
+FOR i = 1 TO 10
+    FOR i = 1 TO 2
+    NEXT i
+NEXT i
+FOR s$ = 1 TO 2
+NEXT s$
+FOR k = 1 TO 2
+NEXT i
+FOR k = 1 STEP 2
+NEXT k
+FOR k = 1 TO 2
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "'i' is already counted by an enclosing FOR loop",
+            "Cannot count with string variable 's$'",
+            "Expected 'NEXT k' to close the FOR loop on line 7, found 'NEXT i'",
+            "Expected 'TO' after 'FOR' start value, found 'STEP'",
+            "Expected 'NEXT', found end of file",
+        ]
+    );
+}
+
+#[test]
+fn test_gosub() {
+    let source = "10 GOSUB 30\n20 GOTO 50\n30 PRINT \"sub\"\n40 RETURN\n50 PRINT \"end\"\n";
+    let program = build_dialect(source, Dialect::LineNumbers).unwrap();
+    assert!(matches!(
+        &program.statements[1],
+        Statement::Gosub { ident, span, .. } if ident == "30" && span.line == 1
+    ));
+    assert!(matches!(
+        &program.statements[7],
+        Statement::Return { span, .. } if *span == Span::new(41, 47, 4, 4)
+    ));
+
+    let source = "10 GOSUB 30\n20 GOTO 50\n30 PRINT \"sub\"\n40 RETURN\n50 GOSUB 60\n";
+    let diagnostics = build_dialect(source, Dialect::LineNumbers).unwrap_err();
+    assert_eq!(
+        diagnostics.errors,
+        vec![CompileError::semantic(
+            "Attempt to GOSUB line 60, which does not exist",
+            Span::new(57, 59, 5, 10)
+        )]
+    );
+
+    let source = "GOSUB sub\nRETURN 1\nLABEL sub\n";
+    let errors = build_source(source).unwrap_err().errors;
+    assert_eq!(
+        errors[0].message(),
+        "Expected newline after 'RETURN', found number 1"
+    );
+    assert_eq!(errors[0].help(), Some("only a FUNCTION can RETURN a value"));
+}
+
+#[test]
+fn test_functions() {
+    let source = "\
+LET total = 1
+PRINT add(total, 2)
+FUNCTION add(a, b)
+    LET sum = a + b
+    RETURN sum
+ENDFUNCTION
+FUNCTION none()
+ENDFUNCTION
+";
+    let program = build_source(source).unwrap();
+    assert_eq!(program.statements.len(), 2);
+    assert_eq!(program.symbols, HashSet::from(["total".to_string()]));
+    let Statement::Print { items, .. } = &program.statements[1] else {
+        panic!("expected PRINT statement");
+    };
+    assert!(matches!(
+        &items[0],
+        PrintItem::Expression(Expression {
+            first_term: Term {
+                unary: Unary::Primary(Primary::Call { name, args, span }),
+                ..
+            },
+            ..
+        }) if name == "add" && args.len() == 2 && *span == Span::new(20, 33, 2, 7)
+    ));
+    let add = &program.functions[0];
+    assert_eq!(add.params, vec!["a", "b"]);
+
+    // Space between a function's name and its '(' is fine, unless the name is a variable.
+    let source = "PRINT add (1, 2)\nFUNCTION add(a, b)\nRETURN a + b\nENDFUNCTION\n";
+    assert!(build_source(source).is_ok());
+    let errors = build_source("LET add = 1\nPRINT add (1, 2)\n")
+        .unwrap_err()
+        .errors;
+    assert_eq!(
+        errors[0].message(),
+        "Expected an operator between terms, found '('"
+    );
+    assert_eq!(add.span, Span::new(43, 46, 3, 10));
+    let mut symbols: Vec<&String> = add.symbols.iter().collect();
+    symbols.sort();
+    assert_eq!(symbols, vec!["a", "b", "sum"]);
+    assert!(matches!(
+        &add.statements[1],
+        Statement::Return { value: Some(_), .. }
+    ));
+    assert!(program.functions[1].params.is_empty());
+
+    let source = "\
+LET a = 1
+FUNCTION f(x, x)
+ENDFUNCTION
+FUNCTION g(x)
+    PRINT a
+    GOSUB top
+    LABEL top
+    FUNCTION h()
+    ENDFUNCTION
+    RETURN
+ENDFUNCTION
+FUNCTION g()
+ENDFUNCTION
+PRINT g (a)
+RETURN a
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Parameter 'x' is already declared",
+            "Undeclared identifier 'a'",
+            "GOSUB cannot be used inside a FUNCTION",
+            "FUNCTION can only be defined at the top level of the program",
+            "Expected the value for FUNCTION 'g' to RETURN, found newline",
+            "Function 'g' is already defined on line 4",
+            "Expected newline after 'RETURN', found identifier 'a'",
+        ]
+    );
+}
+
+#[test]
+fn test_eof_span_counts_characters() {
+    let source = "PRINT \"é\"";
+    let errors = build_source(source).unwrap_err().errors;
+    assert_eq!(errors[0].span().map(|span| span.end), Some(10));
+    // The string is 3 characters but 4 bytes long.
+    assert!(errors[0]
+        .render("eof.tbsc", source)
+        .ends_with("--> eof.tbsc:1:10\n  |\n1 | PRINT \"é\"\n  |          ^\n"));
+}
+
+#[test]
+fn test_statement_spans() {
+    let source = "LET a = 1\nFOR i = 1 TO 2\nNEXT i\nLABEL top\nINPUT a\nGOTO top\n";
+    let program = build_source(source).unwrap();
+    let spans: Vec<(Span, Option<Span>)> = program
+        .statements
+        .iter()
+        .map(|statement| match statement {
+            Statement::Let {
+                span, ident_span, ..
+            }
+            | Statement::For {
+                span, ident_span, ..
+            }
+            | Statement::Label {
+                span, ident_span, ..
+            }
+            | Statement::Input {
+                span, ident_span, ..
+            }
+            | Statement::Goto {
+                span, ident_span, ..
+            } => (*span, Some(*ident_span)),
+            other => panic!("unexpected statement {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (Span::new(0, 3, 1, 1), Some(Span::new(4, 5, 1, 5))),
+            (Span::new(10, 13, 2, 1), Some(Span::new(14, 15, 2, 5))),
+            (Span::new(32, 37, 4, 1), Some(Span::new(38, 41, 4, 7))),
+            (Span::new(42, 47, 5, 1), Some(Span::new(48, 49, 5, 7))),
+            (Span::new(50, 54, 6, 1), Some(Span::new(55, 58, 6, 6))),
+        ]
+    );
+
+    let program = build_dialect("10 PRINT 1\n", Dialect::LineNumbers).unwrap();
+    assert!(matches!(
+        &program.statements[..],
+        [
+            Statement::Label { span, ident_span, .. },
+            Statement::Print { span: print_span, .. },
+        ] if *span == Span::new(0, 2, 1, 1)
+            && ident_span == span
+            && *print_span == Span::new(3, 8, 1, 4)
+    ));
 }
@@ -0,0 +1,634 @@
+use crate::compiler::diagnostic::Span;
+#[cfg(test)]
+use crate::compiler::testing::build_source;
+use crate::compiler::types::{return_type, Type};
+use crate::lexer::{SpannedToken, Token};
+use crate::parser::{
+    Branch, Comparison, Condition, ExprComp, Expression, Function, Primary, PrintItem, Program,
+    Statement, Term, TermComp, Unary,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Version of the schema described in the README. Bumped whenever a change to it could
+/// break a consumer, such as a field being renamed or removed.
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// The text forms the tokens and syntax tree can be written in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Json,
+    /// Compact S-expressions, as in `(let :ident "a" :expression ...)`.
+    Sexp,
+}
+
+/// A value in the machine-readable output, which renders the same way in every format.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Node {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Span(Span),
+    List(Vec<Node>),
+    /// An object of the given kind, with its fields in a fixed order.
+    Object(&'static str, Vec<(&'static str, Node)>),
+}
+
+impl Node {
+    pub fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        match format {
+            Format::Json => self.write_json(&mut out),
+            Format::Sexp => self.write_sexp(&mut out),
+        }
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Node::Null => out.push_str("null"),
+            Node::Bool(value) => write!(out, "{value}").unwrap(),
+            Node::Int(value) => write!(out, "{value}").unwrap(),
+            Node::Float(value) => write_float(out, *value, "null"),
+            Node::Str(text) => write_string(out, text),
+            Node::Span(span) => write!(
+                out,
+                r#"{{"start":{},"end":{},"line":{},"col":{}}}"#,
+                span.start, span.end, span.line, span.col
+            )
+            .unwrap(),
+            Node::List(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write_json(out);
+                }
+                out.push(']');
+            }
+            Node::Object(kind, fields) => {
+                write!(out, r#"{{"kind":"{kind}""#).unwrap();
+                for (name, value) in fields {
+                    write!(out, r#","{name}":"#).unwrap();
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn write_sexp(&self, out: &mut String) {
+        match self {
+            Node::Null => out.push_str("nil"),
+            Node::Bool(value) => out.push_str(if *value { "#t" } else { "#f" }),
+            Node::Int(value) => write!(out, "{value}").unwrap(),
+            Node::Float(value) => write_float(out, *value, "nil"),
+            Node::Str(text) => write_string(out, text),
+            Node::Span(span) => write!(
+                out,
+                "(span {} {} {} {})",
+                span.start, span.end, span.line, span.col
+            )
+            .unwrap(),
+            Node::List(items) => {
+                out.push('(');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    item.write_sexp(out);
+                }
+                out.push(')');
+            }
+            Node::Object(kind, fields) => {
+                write!(out, "({kind}").unwrap();
+                for (name, value) in fields {
+                    write!(out, " :{name} ").unwrap();
+                    value.write_sexp(out);
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// Writes a float so that it reads back as a float, e.g. `2.0` rather than `2`. Neither
+/// format can hold infinities or NaN, which become `missing`.
+fn write_float(out: &mut String, value: f64, missing: &str) {
+    match value.is_finite() {
+        true => write!(out, "{value:?}").unwrap(),
+        false => out.push_str(missing),
+    }
+}
+
+/// Writes `text` as a double-quoted string with JSON escapes, which both formats share.
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn str(text: &str) -> Node {
+    Node::Str(text.to_string())
+}
+
+fn list<T>(items: &[T], node: impl Fn(&T) -> Node) -> Node {
+    Node::List(items.iter().map(node).collect())
+}
+
+fn optional<T>(item: Option<&T>, node: impl Fn(&T) -> Node) -> Node {
+    item.map_or(Node::Null, node)
+}
+
+/// The lexed tokens of a program, in order.
+pub fn tokens(tokens: &[SpannedToken]) -> Node {
+    Node::Object(
+        "tokens",
+        vec![
+            ("version", Node::Int(SCHEMA_VERSION)),
+            ("tokens", list(tokens, token)),
+        ],
+    )
+}
+
+fn token(token: &SpannedToken) -> Node {
+    let value = match &token.token {
+        Token::Ident(text) | Token::StrLit(text) | Token::Invalid(text) => Some(str(text)),
+        Token::Int(value) => Some(Node::Int(i64::from(*value))),
+        Token::Float(value) => Some(Node::Float(*value)),
+        _ => None,
+    };
+    let mut fields = Vec::new();
+    if let Some(value) = value {
+        fields.push(("value", value));
+    }
+    fields.push(("span", Node::Span(token.span)));
+    Node::Object(token_kind(&token.token), fields)
+}
+
+/// Name of the kind of `token`: its variant in snake case.
+fn token_kind(token: &Token) -> &'static str {
+    match token {
+        Token::Add => "add",
+        Token::Sub => "sub",
+        Token::Div => "div",
+        Token::Mul => "mul",
+        Token::Assign => "assign",
+        Token::Equals => "equals",
+        Token::NewLine => "new_line",
+        Token::Gt => "gt",
+        Token::Gte => "gte",
+        Token::Lt => "lt",
+        Token::Lte => "lte",
+        Token::NotEquals => "not_equals",
+        Token::LParen => "l_paren",
+        Token::RParen => "r_paren",
+        Token::Comma => "comma",
+        Token::Semicolon => "semicolon",
+        Token::Ident(_) => "ident",
+        Token::Int(_) => "int",
+        Token::Float(_) => "float",
+        Token::StrLit(_) => "str_lit",
+        Token::And => "and",
+        Token::Else => "else",
+        Token::ElseIf => "else_if",
+        Token::EndFunction => "end_function",
+        Token::EndIf => "end_if",
+        Token::EndWhile => "end_while",
+        Token::For => "for",
+        Token::Function => "function",
+        Token::Gosub => "gosub",
+        Token::Goto => "goto",
+        Token::If => "if",
+        Token::Input => "input",
+        Token::Label => "label",
+        Token::Let => "let",
+        Token::Next => "next",
+        Token::Not => "not",
+        Token::Or => "or",
+        Token::Print => "print",
+        Token::Repeat => "repeat",
+        Token::Return => "return",
+        Token::Step => "step",
+        Token::Then => "then",
+        Token::To => "to",
+        Token::While => "while",
+        Token::Invalid(_) => "invalid",
+    }
+}
+
+/// An operator as written in the source, e.g. `+` or `<=`.
+fn operator(token: &Token) -> Node {
+    let text = match token {
+        Token::Add => "+",
+        Token::Sub => "-",
+        Token::Mul => "*",
+        Token::Div => "/",
+        Token::Equals => "==",
+        Token::NotEquals => "!=",
+        Token::Gt => ">",
+        Token::Gte => ">=",
+        Token::Lt => "<",
+        Token::Lte => "<=",
+        other => unreachable!("{other} is not an operator"),
+    };
+    str(text)
+}
+
+/// The syntax tree of a checked program, with the types inferred for its variables.
+pub fn program(program: &Program) -> Node {
+    Node::Object(
+        "program",
+        vec![
+            ("version", Node::Int(SCHEMA_VERSION)),
+            ("statements", list(&program.statements, statement)),
+            ("functions", list(&program.functions, function)),
+            ("variables", variables(&program.symbols, &program.types)),
+        ],
+    )
+}
+
+fn function(function: &Function) -> Node {
+    Node::Object(
+        "function",
+        vec![
+            ("name", str(&function.name)),
+            ("params", list(&function.params, |param| str(param))),
+            (
+                "returns",
+                str(type_name(return_type(&function.name, &function.types))),
+            ),
+            ("statements", list(&function.statements, statement)),
+            ("variables", variables(&function.symbols, &function.types)),
+            ("span", Node::Span(function.span)),
+        ],
+    )
+}
+
+/// The variables of a program or function, sorted by name, with their types.
+fn variables<'a>(
+    symbols: impl IntoIterator<Item = &'a String>,
+    types: &HashMap<String, Type>,
+) -> Node {
+    let mut names: Vec<&String> = symbols.into_iter().collect();
+    names.sort();
+    list(&names, |name| {
+        Node::Object(
+            "variable",
+            vec![
+                ("name", str(name)),
+                ("type", optional(types.get(*name), |ty| str(type_name(*ty)))),
+            ],
+        )
+    })
+}
+
+fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => "int",
+        Type::Float => "float",
+        Type::Str => "str",
+    }
+}
+
+fn statement(statement: &Statement) -> Node {
+    match statement {
+        Statement::Let {
+            ident,
+            expression: value,
+            span,
+            ident_span,
+        } => Node::Object(
+            "let",
+            vec![
+                ("ident", str(ident)),
+                ("expression", expression(value)),
+                ("span", Node::Span(*span)),
+                ("ident_span", Node::Span(*ident_span)),
+            ],
+        ),
+        Statement::Print {
+            items,
+            newline,
+            span,
+        } => Node::Object(
+            "print",
+            vec![
+                ("items", list(items, print_item)),
+                ("newline", Node::Bool(*newline)),
+                ("span", Node::Span(*span)),
+            ],
+        ),
+        Statement::If {
+            branches,
+            else_statements,
+            span,
+        } => Node::Object(
+            "if",
+            vec![
+                ("branches", list(branches, branch)),
+                (
+                    "else_statements",
+                    optional(else_statements.as_ref(), |statements| {
+                        list(statements, self::statement)
+                    }),
+                ),
+                ("span", Node::Span(*span)),
+            ],
+        ),
+        Statement::While {
+            condition: test,
+            statements,
+            span,
+        } => Node::Object(
+            "while",
+            vec![
+                ("condition", condition(test)),
+                ("statements", list(statements, self::statement)),
+                ("span", Node::Span(*span)),
+            ],
+        ),
+        Statement::For {
+            ident,
+            start,
+            end,
+            step,
+            statements,
+            span,
+            ident_span,
+        } => Node::Object(
+            "for",
+            vec![
+                ("ident", str(ident)),
+                ("start", expression(start)),
+                ("end", expression(end)),
+                ("step", optional(step.as_ref(), |step| expression(step))),
+                ("statements", list(statements, self::statement)),
+                ("span", Node::Span(*span)),
+                ("ident_span", Node::Span(*ident_span)),
+            ],
+        ),
+        Statement::Label {
+            ident,
+            span,
+            ident_span,
+        } => ident_statement("label", ident, *span, *ident_span),
+        Statement::Goto {
+            ident,
+            span,
+            ident_span,
+        } => ident_statement("goto", ident, *span, *ident_span),
+        Statement::Gosub {
+            ident,
+            span,
+            ident_span,
+        } => ident_statement("gosub", ident, *span, *ident_span),
+        Statement::Return { value, span } => Node::Object(
+            "return",
+            vec![
+                ("value", optional(value.as_ref(), expression)),
+                ("span", Node::Span(*span)),
+            ],
+        ),
+        Statement::Input {
+            ident,
+            span,
+            ident_span,
+        } => ident_statement("input", ident, *span, *ident_span),
+    }
+}
+
+/// A statement that consists of a keyword and the name after it.
+fn ident_statement(kind: &'static str, ident: &str, span: Span, ident_span: Span) -> Node {
+    Node::Object(
+        kind,
+        vec![
+            ("ident", str(ident)),
+            ("span", Node::Span(span)),
+            ("ident_span", Node::Span(ident_span)),
+        ],
+    )
+}
+
+fn branch(branch: &Branch) -> Node {
+    Node::Object(
+        "branch",
+        vec![
+            ("condition", condition(&branch.condition)),
+            ("statements", list(&branch.statements, statement)),
+        ],
+    )
+}
+
+fn print_item(item: &PrintItem) -> Node {
+    match item {
+        PrintItem::Expression(value) => expression(value),
+        PrintItem::Tab => Node::Object("tab", vec![]),
+    }
+}
+
+fn condition(condition: &Condition) -> Node {
+    match condition {
+        Condition::Or(left, right) => Node::Object(
+            "or",
+            vec![
+                ("left", self::condition(left)),
+                ("right", self::condition(right)),
+            ],
+        ),
+        Condition::And(left, right) => Node::Object(
+            "and",
+            vec![
+                ("left", self::condition(left)),
+                ("right", self::condition(right)),
+            ],
+        ),
+        Condition::Not(operand) => Node::Object("not", vec![("operand", self::condition(operand))]),
+        Condition::Comparison(comparison) => self::comparison(comparison),
+    }
+}
+
+fn comparison(comparison: &Comparison) -> Node {
+    Node::Object(
+        "comparison",
+        vec![
+            ("left", expression(&comparison.left)),
+            ("operator", operator(&comparison.operator)),
+            ("right", expression(&comparison.right)),
+        ],
+    )
+}
+
+fn expression(expression: &Expression) -> Node {
+    Node::Object(
+        "expression",
+        vec![
+            ("first_term", term(&expression.first_term)),
+            (
+                "other_terms",
+                list(&expression.other_terms, |ExprComp { operator, term }| {
+                    Node::Object(
+                        "expr_comp",
+                        vec![
+                            ("operator", self::operator(operator)),
+                            ("term", self::term(term)),
+                        ],
+                    )
+                }),
+            ),
+            ("span", Node::Span(expression.span)),
+        ],
+    )
+}
+
+fn term(term: &Term) -> Node {
+    Node::Object(
+        "term",
+        vec![
+            ("unary", unary(&term.unary)),
+            (
+                "components",
+                list(&term.components, |TermComp { operator, unary }| {
+                    Node::Object(
+                        "term_comp",
+                        vec![
+                            ("operator", self::operator(operator)),
+                            ("unary", self::unary(unary)),
+                        ],
+                    )
+                }),
+            ),
+        ],
+    )
+}
+
+fn unary(unary: &Unary) -> Node {
+    match unary {
+        Unary::Negate(operand) => Node::Object("negate", vec![("operand", self::unary(operand))]),
+        Unary::Primary(value) => primary(value),
+    }
+}
+
+fn primary(primary: &Primary) -> Node {
+    match primary {
+        Primary::Float(value) => Node::Object("float", vec![("value", Node::Float(*value))]),
+        Primary::Int(value) => Node::Object("int", vec![("value", Node::Int(i64::from(*value)))]),
+        Primary::Str(text) => Node::Object("str", vec![("value", str(text))]),
+        Primary::Ident(name) => Node::Object("ident", vec![("name", str(name))]),
+        Primary::Group(inner) => Node::Object("group", vec![("expression", expression(inner))]),
+        Primary::Call { name, args, span } => Node::Object(
+            "call",
+            vec![
+                ("name", str(name)),
+                ("args", list(args, expression)),
+                ("span", Node::Span(*span)),
+            ],
+        ),
+    }
+}
+
+#[test]
+fn test_tokens() {
+    let tokens = crate::lexer::lex_source("PRINT \"a\\\"b\", x1 + 2.0\n").unwrap();
+    let node = self::tokens(&tokens);
+    assert_eq!(
+        node.render(Format::Json),
+        concat!(
+            r#"{"kind":"tokens","version":1,"tokens":["#,
+            r#"{"kind":"print","span":{"start":0,"end":5,"line":1,"col":1}},"#,
+            r#"{"kind":"str_lit","value":"a\"b","span":{"start":6,"end":12,"line":1,"col":7}},"#,
+            r#"{"kind":"comma","span":{"start":12,"end":13,"line":1,"col":13}},"#,
+            r#"{"kind":"ident","value":"x1","span":{"start":14,"end":16,"line":1,"col":15}},"#,
+            r#"{"kind":"add","span":{"start":17,"end":18,"line":1,"col":18}},"#,
+            r#"{"kind":"float","value":2.0,"span":{"start":19,"end":22,"line":1,"col":20}},"#,
+            r#"{"kind":"new_line","span":{"start":22,"end":23,"line":1,"col":23}}]}"#,
+        )
+    );
+    assert_eq!(
+        node.render(Format::Sexp),
+        concat!(
+            r#"(tokens :version 1 :tokens ((print :span (span 0 5 1 1)) "#,
+            r#"(str_lit :value "a\"b" :span (span 6 12 1 7)) (comma :span (span 12 13 1 13)) "#,
+            r#"(ident :value "x1" :span (span 14 16 1 15)) (add :span (span 17 18 1 18)) "#,
+            r#"(float :value 2.0 :span (span 19 22 1 20)) (new_line :span (span 22 23 1 23))))"#,
+        )
+    );
+}
+
+#[test]
+fn test_program() {
+    let source = "LET n = -1\nFUNCTION f$(a)\nRETURN \"\\t\"\nENDFUNCTION\nPRINT f$(n * 2);\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(
+        self::program(&program).render(Format::Sexp),
+        concat!(
+            "(program :version 1 :statements (",
+            "(let :ident \"n\" :expression (expression ",
+            ":first_term (term :unary (negate :operand (int :value 1)) :components ()) ",
+            ":other_terms () :span (span 8 10 1 9)) ",
+            ":span (span 0 3 1 1) :ident_span (span 4 5 1 5)) ",
+            "(print :items ((expression :first_term (term :unary (call :name \"f$\" :args (",
+            "(expression :first_term (term :unary (ident :name \"n\") :components (",
+            "(term_comp :operator \"*\" :unary (int :value 2)))) ",
+            ":other_terms () :span (span 59 64 5 10))) :span (span 56 65 5 7)) :components ()) ",
+            ":other_terms () :span (span 56 65 5 7))) :newline #f :span (span 50 55 5 1))) ",
+            ":functions ((function :name \"f$\" :params (\"a\") :returns \"str\" :statements (",
+            "(return :value (expression :first_term (term :unary (str :value \"\\t\") ",
+            ":components ()) :other_terms () :span (span 33 37 3 8)) :span (span 26 32 3 1))) ",
+            ":variables ((variable :name \"a\" :type \"float\")) :span (span 20 22 2 10))) ",
+            ":variables ((variable :name \"n\" :type \"int\")))",
+        )
+    );
+    let json = self::program(&program).render(Format::Json);
+    assert!(json.starts_with(r#"{"kind":"program","version":1,"statements":[{"kind":"let","#));
+    assert!(json.contains(r#"{"kind":"print","items":[{"kind":"expression","#));
+    assert!(json.contains(r#""newline":false,"span":{"start":50,"end":55,"line":5,"col":1}}"#));
+    assert!(json.contains(r#"{"kind":"str","value":"\t"}"#));
+}
+
+#[test]
+fn test_function_returns() {
+    // A function that never returns a value still has a type for what it returns, even
+    // before type checking has filled it in.
+    let mut program =
+        build_source("FUNCTION none()\nENDFUNCTION\nFUNCTION none$()\nENDFUNCTION\n").unwrap();
+    for function in &mut program.functions {
+        function.types.clear();
+    }
+    let json = self::program(&program).render(Format::Json);
+    assert!(json.contains(r#""name":"none","params":[],"returns":"float""#));
+    assert!(json.contains(r#""name":"none$","params":[],"returns":"str""#));
+}
+
+#[test]
+fn test_render_values() {
+    let node = Node::List(vec![
+        Node::Null,
+        Node::Bool(true),
+        Node::Float(f64::INFINITY),
+        Node::Float(0.5),
+        Node::Str("\u{1}é".to_string()),
+        Node::Object("tab", vec![]),
+    ]);
+    assert_eq!(
+        node.render(Format::Json),
+        r#"[null,true,null,0.5,"\u0001é",{"kind":"tab"}]"#
+    );
+    assert_eq!(
+        node.render(Format::Sexp),
+        r#"(nil #t nil 0.5 "\u0001é" (tab))"#
+    );
+}
@@ -0,0 +1,15 @@
+//! Helpers shared by the tests of the compiler passes.
+
+use crate::compiler::diagnostic::Diagnostics;
+use crate::compiler::parser::{Dialect, Program};
+
+/// Parses and checks `source`, returning the program, or the errors found in it along
+/// with any warnings.
+pub fn build_source(source: &str) -> Result<Program, Diagnostics> {
+    build_dialect(source, Dialect::default())
+}
+
+/// Like `build_source`, for source written in `dialect`.
+pub fn build_dialect(source: &str, dialect: Dialect) -> Result<Program, Diagnostics> {
+    crate::Options::new().dialect(dialect).parse(source)
+}
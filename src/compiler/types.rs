@@ -0,0 +1,900 @@
+use crate::compiler::diagnostic::{Diagnostic, Span};
+use crate::compiler::error::CompileError;
+#[cfg(test)]
+use crate::compiler::testing::build_source;
+use crate::lexer::Token;
+use crate::parser::{
+    Comparison, Condition, Expression, Function, Primary, PrintItem, Statement, Term, Unary,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The type of a variable or expression. Variables never change type: one that is
+/// assigned a float anywhere in the program is a float everywhere.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Type {
+    Int,
+    Float,
+    /// Text. Variables holding it are named with a trailing `$`, as in `name$`.
+    Str,
+}
+
+impl Type {
+    /// The type able to hold values of both `self` and `other`.
+    pub fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (Type::Int, Type::Int) => Type::Int,
+            (Type::Str, Type::Str) => Type::Str,
+            _ => Type::Float,
+        }
+    }
+
+    /// Name of the type in the generated C.
+    pub fn c_name(&self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "double",
+            Type::Str => "char *",
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "integer"),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "string"),
+        }
+    }
+}
+
+/// Whether `ident` names a string variable, or a function returning a string.
+pub fn is_string_ident(ident: &str) -> bool {
+    ident.ends_with('$')
+}
+
+/// Key under which the type of what function `name` returns is kept, alongside the types
+/// of the variables. The parentheses keep it apart from any variable of the same name.
+pub fn return_key(name: &str) -> String {
+    format!("{name}()")
+}
+
+/// Type of what function `name` returns according to `types`, or if they don't say, the
+/// type it would have if it never returned a value: a string or a double, by its name.
+pub fn return_type(name: &str, types: &HashMap<String, Type>) -> Type {
+    match types.get(&return_key(name)) {
+        Some(ty) => *ty,
+        None if is_string_ident(name) => Type::Str,
+        None => Type::Float,
+    }
+}
+
+/// Outcome of type checking a program.
+#[derive(Debug, Default)]
+pub struct TypeCheck {
+    pub types: HashMap<String, Type>,
+    /// Types for the body of each function, in the order the functions were given.
+    pub function_types: Vec<HashMap<String, Type>>,
+    pub warnings: Vec<Diagnostic>,
+    pub errors: Vec<CompileError>,
+}
+
+/// What checking the main program or the body of a function needs to know.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    types: &'a HashMap<String, Type>,
+    /// Every function in the program, to check calls against. `None` once the program is
+    /// known to be valid, and only the type of an expression is wanted.
+    functions: Option<&'a [Function]>,
+    /// The function whose body is being checked, if any.
+    function: Option<&'a Function>,
+}
+
+/// Works out the type of every symbol from the values assigned to it, then checks
+/// that every expression makes sense for the types involved.
+///
+/// A numeric variable is an integer if everything ever assigned to it is. `INPUT`
+/// counts as assigning a float, so that typing `2.5` means what it says. Numeric
+/// parameters are always floats, so that any number can be passed, and a function
+/// returns whatever its RETURNs give, as if they were assignments.
+///
+/// Variables in `known`, such as those of earlier REPL entries, start out with the type
+/// given there and can only widen from it.
+pub fn infer(
+    statements: &[Statement],
+    symbols: &HashSet<String>,
+    known: &HashMap<String, Type>,
+    functions: &[Function],
+) -> TypeCheck {
+    // A function's return type can depend on what the functions it calls return, so
+    // these settle the same way as variable types.
+    let mut returns: HashMap<String, Type> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for function in functions {
+            let (types, _) = infer_scope(
+                &function.statements,
+                &function.symbols,
+                Some(function),
+                &returns,
+            );
+            let key = return_key(&function.name);
+            if let Some(returned) = types.get(&key).copied() {
+                if returns.insert(key, returned) != Some(returned) {
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // Only functions that return nothing but calls to themselves, if anything, are left.
+    for function in functions {
+        let returned = return_type(&function.name, &returns);
+        returns.insert(return_key(&function.name), returned);
+    }
+
+    let mut check = TypeCheck::default();
+    let mut settled = known.clone();
+    settled.extend(returns.clone());
+    let (types, assignments) = infer_scope(statements, symbols, None, &settled);
+    let scope = Scope {
+        types: &types,
+        functions: Some(functions),
+        function: None,
+    };
+    check_statements(statements, scope, &mut check.errors);
+    let mut warnings = conflict_warnings(&assignments, &types);
+    for function in functions {
+        let (function_types, assignments) = infer_scope(
+            &function.statements,
+            &function.symbols,
+            Some(function),
+            &returns,
+        );
+        let scope = Scope {
+            types: &function_types,
+            functions: Some(functions),
+            function: Some(function),
+        };
+        check_statements(&function.statements, scope, &mut check.errors);
+        warnings.extend(conflict_warnings(&assignments, &function_types));
+        check.function_types.push(function_types);
+    }
+    check
+        .errors
+        .sort_by_key(|err| err.span().map(|span| span.start));
+    if check.errors.is_empty() {
+        warnings.sort_by_key(|warning| warning.span.start);
+        check.warnings = warnings;
+    }
+    check.types = types;
+    check
+}
+
+/// Types of the symbols of the main program, or of `function`, given the return types of
+/// the functions settled so far. Also returns the assignments they were worked out from.
+fn infer_scope<'a>(
+    statements: &'a [Statement],
+    symbols: &HashSet<String>,
+    function: Option<&Function>,
+    returns: &HashMap<String, Type>,
+) -> (HashMap<String, Type>, Vec<(&'a str, &'a Expression)>) {
+    let mut assignments = Vec::new();
+    let mut inputs = Vec::new();
+    let mut returned = Vec::new();
+    collect_assignments(statements, &mut assignments, &mut inputs, &mut returned);
+
+    let mut types = returns.clone();
+    types.extend(
+        symbols
+            .iter()
+            .filter(|ident| is_string_ident(ident))
+            .map(|ident| (ident.clone(), Type::Str)),
+    );
+    let params = function.map(|function| function.params.as_slice());
+    for ident in inputs
+        .iter()
+        .copied()
+        .chain(params.unwrap_or_default().iter().map(String::as_str))
+        .filter(|ident| !is_string_ident(ident))
+    {
+        types.insert(ident.to_string(), Type::Float);
+    }
+
+    // What a function RETURNs counts as assigned to its entry in `types`, so that a
+    // recursive call sees the return type settled so far.
+    let key = function.map(|function| return_key(&function.name));
+    let mut settling = assignments.clone();
+    if let (Some(key), Some(function)) = (&key, function) {
+        if !is_string_ident(&function.name) {
+            settling.extend(
+                returned
+                    .iter()
+                    .map(|expression| (key.as_str(), *expression)),
+            );
+        }
+    }
+
+    // Types only ever widen from integer to float, so this settles quickly.
+    loop {
+        let mut changed = false;
+        for (ident, expression) in &settling {
+            if is_string_ident(ident) {
+                continue;
+            }
+            let Some(assigned) = partial_type(expression, &types) else {
+                continue;
+            };
+            let joined = match types.get(*ident) {
+                Some(current) => current.join(assigned),
+                None => assigned,
+            };
+            if types.insert(ident.to_string(), joined) != Some(joined) {
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // Only variables assigned nothing but themselves, as in `LET a = a + 1`, are left.
+    for symbol in symbols {
+        types.entry(symbol.clone()).or_insert(Type::Float);
+    }
+    (types, assignments)
+}
+
+/// Warns about computed integers that end up in float variables.
+fn conflict_warnings(
+    assignments: &[(&str, &Expression)],
+    types: &HashMap<String, Type>,
+) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let mut warned = HashSet::new();
+    for (ident, expression) in assignments {
+        if types[*ident] != Type::Float || warned.contains(ident) {
+            continue;
+        }
+        // Plain integer constants such as `LET total = 0` are fine in a float; it's a
+        // computed integer turning into a float that is worth pointing out.
+        let int_span = assignments
+            .iter()
+            .filter(|(other, _)| other == ident)
+            .find(|(_, expr)| expression_type(expr, types) == Type::Int && !is_constant(expr))
+            .map(|(_, expr)| expr.span);
+        if let (Some(int_span), Type::Float) = (int_span, expression_type(expression, types)) {
+            warned.insert(ident);
+            warnings.push(conflict_warning(ident, expression.span, int_span));
+        }
+    }
+    warnings
+}
+
+fn conflict_warning(ident: &str, float_span: Span, int_span: Span) -> Diagnostic {
+    let mut warning = Diagnostic::warning(
+        format!("Variable '{ident}' is assigned both integer and float values"),
+        float_span,
+    );
+    warning.help = Some(format!(
+        "'{ident}' will be a float everywhere, including the integer value assigned on line {}",
+        int_span.line
+    ));
+    warning
+}
+
+/// Every `LET`, `INPUT` and value `RETURN`ed in the program, however deeply nested.
+fn collect_assignments<'a>(
+    statements: &'a [Statement],
+    assignments: &mut Vec<(&'a str, &'a Expression)>,
+    inputs: &mut Vec<&'a str>,
+    returned: &mut Vec<&'a Expression>,
+) {
+    for statement in statements {
+        match statement {
+            Statement::Let {
+                ident, expression, ..
+            } => assignments.push((ident, expression)),
+            Statement::Input { ident, .. } => inputs.push(ident),
+            Statement::Return {
+                value: Some(value), ..
+            } => returned.push(value),
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => {
+                for branch in branches {
+                    collect_assignments(&branch.statements, assignments, inputs, returned);
+                }
+                if let Some(statements) = else_statements {
+                    collect_assignments(statements, assignments, inputs, returned);
+                }
+            }
+            Statement::While { statements, .. } => {
+                collect_assignments(statements, assignments, inputs, returned)
+            }
+            // The step is added to the variable, so it counts towards its type too.
+            Statement::For {
+                ident,
+                start,
+                step,
+                statements,
+                ..
+            } => {
+                assignments.push((ident, start));
+                if let Some(step) = step {
+                    assignments.push((ident, step));
+                }
+                collect_assignments(statements, assignments, inputs, returned)
+            }
+            Statement::Print { .. }
+            | Statement::Label { .. }
+            | Statement::Goto { .. }
+            | Statement::Gosub { .. }
+            | Statement::Return { .. } => {}
+        }
+    }
+}
+
+/// Type of a numeric `expression` going by the variables and functions typed so far, or
+/// `None` if it only uses ones that aren't, or isn't numeric at all.
+fn partial_type(expression: &Expression, types: &HashMap<String, Type>) -> Option<Type> {
+    let mut result: Option<Type> = None;
+    for operand in operands(expression) {
+        let operand = match operand {
+            Primary::Int(_) => Some(Type::Int),
+            Primary::Float(_) => Some(Type::Float),
+            Primary::Str(_) => return None,
+            Primary::Ident(ident) | Primary::Call { name: ident, .. } if is_string_ident(ident) => {
+                return None
+            }
+            Primary::Ident(ident) => types.get(ident).copied(),
+            Primary::Call { name, .. } => types.get(&return_key(name)).copied(),
+            Primary::Group(_) => unreachable!("groups are flattened by operands()"),
+        };
+        result = match (result, operand) {
+            (Some(ty), Some(other)) => Some(ty.join(other)),
+            (ty, other) => ty.or(other),
+        };
+    }
+    result
+}
+
+/// Reports every assignment, comparison, operation and call that mixes types it can't.
+fn check_statements(statements: &[Statement], scope: Scope, errors: &mut Vec<CompileError>) {
+    for statement in statements {
+        let result = match statement {
+            Statement::Let {
+                ident, expression, ..
+            } => {
+                check_expression(expression, scope).and_then(|ty| match (scope.types[ident], ty) {
+                    (Type::Str, Type::Int | Type::Float) => Err(CompileError::semantic(
+                        format!("Cannot assign a number to string variable '{ident}'"),
+                        expression.span,
+                    )),
+                    (Type::Int | Type::Float, Type::Str) => Err(CompileError::semantic(
+                        format!(
+                            "Cannot assign a string to numeric variable '{ident}'; \
+                                 string variable names end in '$'"
+                        ),
+                        expression.span,
+                    )),
+                    _ => Ok(()),
+                })
+            }
+            Statement::Print { items, .. } => items
+                .iter()
+                .filter_map(|item| match item {
+                    PrintItem::Expression(expression) => Some(expression),
+                    PrintItem::Tab => None,
+                })
+                .try_for_each(|expression| check_expression(expression, scope).map(|_| ())),
+            Statement::If {
+                branches,
+                else_statements,
+                ..
+            } => {
+                for branch in branches {
+                    check_statements(&branch.statements, scope, errors);
+                }
+                if let Some(statements) = else_statements {
+                    check_statements(statements, scope, errors);
+                }
+                branches
+                    .iter()
+                    .try_for_each(|branch| check_condition(&branch.condition, scope))
+            }
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => {
+                check_statements(statements, scope, errors);
+                check_condition(condition, scope)
+            }
+            Statement::For {
+                start,
+                end,
+                step,
+                statements,
+                ..
+            } => {
+                check_statements(statements, scope, errors);
+                [Some(start), Some(end), step.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .try_for_each(|expression| match check_expression(expression, scope)? {
+                        Type::Str => Err(CompileError::semantic(
+                            "FOR loops can only count with numbers",
+                            expression.span,
+                        )),
+                        _ => Ok(()),
+                    })
+            }
+            Statement::Return {
+                value: Some(value), ..
+            } => check_expression(value, scope).and_then(|ty| {
+                let name = scope.function.map_or("", |function| function.name.as_str());
+                match (is_string_ident(name), ty) {
+                    (false, Type::Str) => Err(CompileError::semantic(
+                        format!(
+                            "Function '{name}' cannot RETURN a string; \
+                             functions returning strings are named with a trailing '$'"
+                        ),
+                        value.span,
+                    )),
+                    (true, Type::Int | Type::Float) => Err(CompileError::semantic(
+                        format!("Function '{name}' returns a string, so cannot RETURN a number"),
+                        value.span,
+                    )),
+                    _ => Ok(()),
+                }
+            }),
+            Statement::Label { .. }
+            | Statement::Goto { .. }
+            | Statement::Gosub { .. }
+            | Statement::Return { .. }
+            | Statement::Input { .. } => Ok(()),
+        };
+        if let Err(err) = result {
+            errors.push(err);
+        }
+    }
+    errors.sort_by_key(|err| err.span().map(|span| span.start));
+}
+
+fn check_condition(condition: &Condition, scope: Scope) -> Result<(), CompileError> {
+    match condition {
+        Condition::Or(left, right) | Condition::And(left, right) => {
+            check_condition(left, scope)?;
+            check_condition(right, scope)
+        }
+        Condition::Not(condition) => check_condition(condition, scope),
+        Condition::Comparison(comparison) => check_comparison(comparison, scope),
+    }
+}
+
+fn check_comparison(comparison: &Comparison, scope: Scope) -> Result<(), CompileError> {
+    let left = check_expression(&comparison.left, scope)?;
+    let right = check_expression(&comparison.right, scope)?;
+    let span = comparison.left.span.to(comparison.right.span);
+    match (left, right, &comparison.operator) {
+        (Type::Str, Type::Str, Token::Equals | Token::NotEquals) => Ok(()),
+        (Type::Str, Type::Str, _) => Err(CompileError::semantic(
+            "Strings can only be compared with '==' or '!='",
+            span,
+        )),
+        (Type::Str, _, _) | (_, Type::Str, _) => Err(CompileError::semantic(
+            "Cannot compare a string with a number",
+            span,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Type of `expression`, or an error if it combines values that can't be combined.
+/// As in C, an operation on two integers is an integer, so `7 / 2` is `3`.
+fn check_expression(expression: &Expression, scope: Scope) -> Result<Type, CompileError> {
+    let span = expression.span;
+    let mut ty = check_term(&expression.first_term, scope, span)?;
+    for comp in &expression.other_terms {
+        let other = check_term(&comp.term, scope, span)?;
+        ty = combine(ty, &comp.operator, other)
+            .map_err(|message| CompileError::semantic(message, span))?;
+    }
+    Ok(ty)
+}
+
+/// Like `check_expression`, for a term of the expression at `span`.
+fn check_term(term: &Term, scope: Scope, span: Span) -> Result<Type, CompileError> {
+    let mut ty = check_unary(&term.unary, scope, span)?;
+    for comp in &term.components {
+        let other = check_unary(&comp.unary, scope, span)?;
+        ty = combine(ty, &comp.operator, other)
+            .map_err(|message| CompileError::semantic(message, span))?;
+    }
+    Ok(ty)
+}
+
+fn check_unary(unary: &Unary, scope: Scope, span: Span) -> Result<Type, CompileError> {
+    match unary {
+        Unary::Negate(inner) => match check_unary(inner, scope, span)? {
+            Type::Str => Err(CompileError::semantic("Cannot negate a string", span)),
+            ty => Ok(ty),
+        },
+        Unary::Primary(Primary::Int(_)) => Ok(Type::Int),
+        Unary::Primary(Primary::Float(_)) => Ok(Type::Float),
+        Unary::Primary(Primary::Str(_)) => Ok(Type::Str),
+        Unary::Primary(Primary::Ident(ident)) => {
+            Ok(scope.types.get(ident).copied().unwrap_or(Type::Float))
+        }
+        Unary::Primary(Primary::Group(inner)) => check_expression(inner, scope),
+        Unary::Primary(Primary::Call { name, args, span }) => {
+            if let Some(functions) = scope.functions {
+                check_call(name, args, *span, functions, scope)?;
+            }
+            Ok(return_type(name, scope.types))
+        }
+    }
+}
+
+/// Checks that function `name` exists, and is given the right number and kinds of
+/// arguments.
+fn check_call(
+    name: &str,
+    args: &[Expression],
+    span: Span,
+    functions: &[Function],
+    scope: Scope,
+) -> Result<(), CompileError> {
+    let Some(function) = functions.iter().find(|function| function.name == name) else {
+        return Err(CompileError::semantic(
+            format!("Undefined function '{name}'"),
+            span,
+        ));
+    };
+    if args.len() != function.params.len() {
+        let plural = if function.params.len() == 1 { "" } else { "s" };
+        return Err(CompileError::semantic(
+            format!(
+                "Expected {} argument{plural} for '{name}', found {}",
+                function.params.len(),
+                args.len()
+            ),
+            span,
+        ));
+    }
+    for (arg, param) in args.iter().zip(&function.params) {
+        match (is_string_ident(param), check_expression(arg, scope)?) {
+            (true, Type::Int | Type::Float) => {
+                return Err(CompileError::semantic(
+                    format!("Parameter '{param}' of '{name}' takes a string, not a number"),
+                    arg.span,
+                ))
+            }
+            (false, Type::Str) => {
+                return Err(CompileError::semantic(
+                    format!("Parameter '{param}' of '{name}' takes a number, not a string"),
+                    arg.span,
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Type of `left operator right`. Strings only support `+`, which joins them.
+fn combine(left: Type, operator: &Token, right: Type) -> Result<Type, String> {
+    match (left, right) {
+        (Type::Str, Type::Str) if *operator == Token::Add => Ok(Type::Str),
+        (Type::Str, Type::Str) => Err(format!("Operator {operator} cannot be used on strings")),
+        (Type::Str, _) | (_, Type::Str) => Err(format!(
+            "Operator {operator} cannot combine a string with a number"
+        )),
+        (left, right) => Ok(left.join(right)),
+    }
+}
+
+/// Type of `expression` in a program that has passed type checking.
+pub fn expression_type(expression: &Expression, types: &HashMap<String, Type>) -> Type {
+    let scope = Scope {
+        types,
+        functions: None,
+        function: None,
+    };
+    check_expression(expression, scope).unwrap_or(Type::Float)
+}
+
+/// Type of `term`, a term of an expression in a program that has passed type checking.
+pub fn term_type(term: &Term, types: &HashMap<String, Type>) -> Type {
+    let scope = Scope {
+        types,
+        functions: None,
+        function: None,
+    };
+    check_term(term, scope, Span::default()).unwrap_or(Type::Float)
+}
+
+/// Type of `unary`, an operand in a program that has passed type checking.
+pub fn unary_type(unary: &Unary, types: &HashMap<String, Type>) -> Type {
+    let scope = Scope {
+        types,
+        functions: None,
+        function: None,
+    };
+    check_unary(unary, scope, Span::default()).unwrap_or(Type::Float)
+}
+
+/// Whether `expression`, in a program that has passed type checking, is a string. Unlike
+/// `expression_type` this needs no types, since strings can be told apart by name.
+pub fn is_string_expression(expression: &Expression) -> bool {
+    operands(expression).iter().any(|operand| match operand {
+        Primary::Str(_) => true,
+        Primary::Ident(ident) | Primary::Call { name: ident, .. } => is_string_ident(ident),
+        _ => false,
+    })
+}
+
+fn is_constant(expression: &Expression) -> bool {
+    operands(expression).iter().all(|operand| {
+        matches!(
+            operand,
+            Primary::Int(_) | Primary::Float(_) | Primary::Str(_)
+        )
+    })
+}
+
+/// The literals, variables and calls in `expression`, looking inside parentheses but not
+/// into the arguments of calls.
+fn operands(expression: &Expression) -> Vec<&Primary> {
+    let mut found = Vec::new();
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        let terms = std::iter::once(&expression.first_term)
+            .chain(expression.other_terms.iter().map(|comp| &comp.term));
+        for Term { unary, components } in terms {
+            let unaries = std::iter::once(unary).chain(components.iter().map(|comp| &comp.unary));
+            for mut unary in unaries {
+                while let Unary::Negate(inner) = unary {
+                    unary = inner;
+                }
+                match unary {
+                    Unary::Primary(Primary::Group(inner)) => pending.push(inner),
+                    Unary::Primary(primary) => found.push(primary),
+                    Unary::Negate(_) => unreachable!(),
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Builds `source` and returns the inferred type of each of `idents`, plus any warnings.
+#[cfg(test)]
+fn infer_source(source: &str, idents: &[&str]) -> (Vec<Type>, Vec<String>) {
+    let program = build_source(source).unwrap();
+    let types = idents.iter().map(|ident| program.types[*ident]).collect();
+    let warnings = program
+        .warnings
+        .iter()
+        .map(|warning| warning.message.clone())
+        .collect();
+    (types, warnings)
+}
+
+#[test]
+fn test_infer_types() {
+    let source = "\
+LET i = 1
+LET f = 1.5
+LET j = i * 2 / 3
+LET g = i / 2.0
+INPUT n
+LET total = 0
+LET total = total + n
+";
+    let (types, warnings) = infer_source(source, &["i", "f", "j", "g", "n", "total"]);
+    assert_eq!(
+        types,
+        vec![
+            Type::Int,
+            Type::Float,
+            Type::Int,
+            Type::Float,
+            Type::Float,
+            Type::Float
+        ]
+    );
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
+
+#[test]
+fn test_infer_through_loops() {
+    // `b` only becomes a float after `a` does, which is assigned further down.
+    let source = "\
+LET a = 1
+LET b = 0
+LABEL top
+LET b = a + 1
+LET a = 0.5
+IF b < 3 THEN
+    GOTO top
+ENDIF
+";
+    let (types, _) = infer_source(source, &["a", "b"]);
+    assert_eq!(types, vec![Type::Float, Type::Float]);
+}
+
+#[test]
+fn test_infer_for_loops() {
+    let source = "\
+FOR i = 1 TO 2.5
+    FOR x = 0 TO 1 STEP 0.25
+    NEXT x
+    FOR j = 10 TO i STEP -2
+    NEXT j
+NEXT i
+";
+    let (types, warnings) = infer_source(source, &["i", "x", "j"]);
+    assert_eq!(types, vec![Type::Int, Type::Float, Type::Int]);
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
+
+#[test]
+fn test_type_conflict() {
+    let source = "LET n = 7\nLET half = n / 2\nLET half = 0.5\n";
+    let program = build_source(source).unwrap();
+    assert_eq!(program.types["half"], Type::Float);
+    assert_eq!(program.warnings.len(), 1);
+    let warning = &program.warnings[0];
+    assert_eq!(
+        warning.message,
+        "Variable 'half' is assigned both integer and float values"
+    );
+    assert_eq!(warning.span, Span::new(38, 41, 3, 12));
+    assert_eq!(
+        warning.help.as_deref(),
+        Some("'half' will be a float everywhere, including the integer value assigned on line 2")
+    );
+}
+
+#[test]
+fn test_expression_type() {
+    let types = HashMap::from([("i".to_string(), Type::Int), ("f".to_string(), Type::Float)]);
+    let program = build_source("LET i = 1\nLET f = 1.0\nLET x = -(i * 2) / i\n").unwrap();
+    let Statement::Let { expression, .. } = &program.statements[2] else {
+        panic!("expected LET statement");
+    };
+    assert_eq!(expression_type(expression, &types), Type::Int);
+    assert_eq!(Type::Int.join(Type::Float), Type::Float);
+    assert_eq!(Type::Float.c_name(), "double");
+}
+
+#[test]
+fn test_string_types() {
+    let (types, _) = infer_source("LET a$ = \"x\" + \"y\"\nINPUT b$\n", &["a$", "b$"]);
+    assert_eq!(types, vec![Type::Str, Type::Str]);
+}
+
+#[test]
+fn test_type_errors() {
+    let source = "\
+LET n = 1
+LET s$ = \"a\"
+LET s$ = n
+LET n = s$
+LET t$ = s$ * s$
+LET t$ = s$ + 1
+LET t$ = -s$
+IF s$ < \"b\" THEN
+ENDIF
+WHILE s$ == n REPEAT
+ENDWHILE
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<(&str, u32)> = errors
+        .iter()
+        .map(|err| (err.message(), err.span().unwrap().line))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            ("Cannot assign a number to string variable 's$'", 3),
+            (
+                "Cannot assign a string to numeric variable 'n'; string variable names end in '$'",
+                4
+            ),
+            ("Operator '*' cannot be used on strings", 5),
+            ("Operator '+' cannot combine a string with a number", 6),
+            ("Cannot negate a string", 7),
+            ("Strings can only be compared with '==' or '!='", 8),
+            ("Cannot compare a string with a number", 10),
+        ]
+    );
+}
+
+#[test]
+fn test_function_types() {
+    let source = "\
+FUNCTION one()
+    RETURN 1
+ENDFUNCTION
+FUNCTION half(x)
+    RETURN x / 2
+ENDFUNCTION
+FUNCTION count(n)
+    IF n > 0 THEN
+        RETURN count(n - 1) + one()
+    ENDIF
+    RETURN 0
+ENDFUNCTION
+FUNCTION name$()
+    RETURN \"a\"
+ENDFUNCTION
+LET i = one()
+LET f = half(i)
+LET c = count(3)
+LET s$ = name$()
+";
+    let program = build_source(source).unwrap();
+    let returned: Vec<Type> = ["one", "half", "count", "name$"]
+        .iter()
+        .map(|name| program.types[&return_key(name)])
+        .collect();
+    assert_eq!(returned, vec![Type::Int, Type::Float, Type::Int, Type::Str]);
+    let variables: Vec<Type> = ["i", "f", "c"]
+        .iter()
+        .map(|ident| program.types[*ident])
+        .collect();
+    assert_eq!(variables, vec![Type::Int, Type::Float, Type::Int]);
+    // Numeric parameters are floats, whatever is passed to them.
+    assert_eq!(program.functions[2].types["n"], Type::Float);
+}
+
+#[test]
+fn test_call_errors() {
+    let source = "\
+FUNCTION f(a, b$)
+    RETURN b$
+ENDFUNCTION
+FUNCTION g$(a)
+    RETURN a
+ENDFUNCTION
+LET x = f(1)
+LET x = f(\"a\", \"b\")
+LET x = f(1, 2)
+LET x = h(1)
+LET x = g$(1)
+";
+    let errors = build_source(source).unwrap_err().errors;
+    let messages: Vec<(&str, u32)> = errors
+        .iter()
+        .map(|err| (err.message(), err.span().unwrap().line))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (
+                "Function 'f' cannot RETURN a string; \
+                 functions returning strings are named with a trailing '$'",
+                2
+            ),
+            (
+                "Function 'g$' returns a string, so cannot RETURN a number",
+                5
+            ),
+            ("Expected 2 arguments for 'f', found 1", 7),
+            ("Parameter 'a' of 'f' takes a number, not a string", 8),
+            ("Parameter 'b$' of 'f' takes a string, not a number", 9),
+            ("Undefined function 'h'", 10),
+            (
+                "Cannot assign a string to numeric variable 'x'; string variable names end in '$'",
+                11
+            ),
+        ]
+    );
+}
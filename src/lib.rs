@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::Path;
+
+use crate::compiler::lexer::SpannedToken;
+use crate::compiler::{emitter, lexer, parser};
+use crate::toolchain::Toolchain;
+
+pub use crate::compiler::diagnostic::{Diagnostic, Diagnostics};
+pub use crate::compiler::error::CompileError;
+pub use crate::compiler::parser::{Dialect, Program};
+
+pub mod compiler;
+pub mod repl;
+pub mod toolchain;
+
+/// How to compile a program, set one option at a time, as in
+/// `Options::new().dialect(Dialect::LineNumbers).optimize("2")`.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    dialect: Dialect,
+    cc: Option<String>,
+    optimize: Option<String>,
+    cflags: Vec<String>,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// The flavour of Tiny BASIC the source is written in.
+    pub fn dialect(mut self, dialect: Dialect) -> Options {
+        self.dialect = dialect;
+        self
+    }
+
+    /// C compiler to build with, instead of the one in `CC` or the first one installed.
+    pub fn cc(mut self, cc: impl Into<String>) -> Options {
+        self.cc = Some(cc.into());
+        self
+    }
+
+    /// Optimization level to pass to the C compiler, e.g. `"2"` for `-O2`.
+    pub fn optimize(mut self, level: impl Into<String>) -> Options {
+        self.optimize = Some(level.into());
+        self
+    }
+
+    /// Adds a flag to pass to the C compiler, after any in `CFLAGS`.
+    pub fn cflag(mut self, flag: impl Into<String>) -> Options {
+        self.cflags.push(flag.into());
+        self
+    }
+
+    /// Parses and checks `source`. Warnings about a program that compiles are left in
+    /// its `warnings`.
+    pub fn parse(&self, source: &str) -> Result<Program, Diagnostics> {
+        let tokens = lexer::lex_source(source)?;
+        let mut program = Program::new();
+        program.dialect = self.dialect;
+        match program.build(tokens) {
+            Ok(()) => Ok(program),
+            Err(errors) => Err(Diagnostics {
+                errors,
+                warnings: program.warnings,
+            }),
+        }
+    }
+
+    pub fn compile_to_c(&self, source: &str) -> Result<String, Diagnostics> {
+        self.parse(source).map(emit_c)
+    }
+
+    /// Compiles a parsed program to an executable at `output`, keeping its C code beside
+    /// it with a `.c` extension. Any missing directories are created.
+    pub fn build(&self, program: Program, output: &Path) -> Result<(), CompileError> {
+        let c_path = output.with_extension("c");
+        if c_path == output {
+            return Err(CompileError::backend(format!(
+                "the executable cannot be written to {}, where its C code goes",
+                output.display()
+            )));
+        }
+        let write_error = |err: std::io::Error| {
+            CompileError::backend(format!("unable to write {}: {err}", c_path.display()))
+        };
+        if let Some(dir) = c_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(write_error)?;
+        }
+        fs::write(&c_path, emit_c(program)).map_err(write_error)?;
+        Toolchain::new(self.cc.clone(), self.optimize.clone(), self.cflags.clone())?
+            .compile(&c_path, output)
+    }
+}
+
+/// Splits `source` into tokens.
+pub fn lex(source: &str) -> Result<Vec<SpannedToken>, Diagnostics> {
+    Ok(lexer::lex_source(source)?)
+}
+
+/// Parses and checks `source` with the default options.
+pub fn parse(source: &str) -> Result<Program, Diagnostics> {
+    Options::new().parse(source)
+}
+
+/// Compiles `source` to C with the default options.
+pub fn compile_to_c(source: &str) -> Result<String, Diagnostics> {
+    Options::new().compile_to_c(source)
+}
+
+/// The C code for a parsed program.
+pub fn emit_c(program: Program) -> String {
+    let mut emitter = emitter::Emitter::new();
+    emitter.build(program);
+    emitter.to_string()
+}
+
+#[test]
+fn test_compile_to_c() {
+    let c_code = compile_to_c("LET a = 1\nPRINT a + 1\n").unwrap();
+    assert!(c_code.contains("int main(void){\n"));
+    assert!(c_code.contains("a = 1;\n"));
+
+    let diagnostics = compile_to_c("PRINT x\nLET = 2\n").unwrap_err();
+    let messages: Vec<&str> = diagnostics.errors.iter().map(|err| err.message()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Undeclared identifier 'x'",
+            "Expected identifier after 'LET', found '='"
+        ]
+    );
+}
+
+#[test]
+fn test_options() {
+    let source = "10 PRINT \"HI\"\n20 GOTO 10\n";
+    assert!(parse(source).is_err());
+    let program = Options::new()
+        .dialect(Dialect::LineNumbers)
+        .parse(source)
+        .unwrap();
+    assert_eq!(program.statements.len(), 4);
+
+    let options = Options::new().cc("clang").optimize("2").cflag("-Wall");
+    assert_eq!(options.cc.as_deref(), Some("clang"));
+    assert_eq!(options.optimize.as_deref(), Some("2"));
+    assert_eq!(options.cflags, vec!["-Wall"]);
+
+    let err = options.build(program, Path::new("listing.c")).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "the executable cannot be written to listing.c, where its C code goes"
+    );
+}
@@ -1,134 +1,359 @@
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
 
-use clap::{CommandFactory, Parser};
+use clap::{Parser, Subcommand};
 
-use crate::compiler::{emitter, lexer, parser};
+use tnybsc::compiler::interpreter::Interpreter;
+use tnybsc::compiler::lexer::SpannedToken;
+use tnybsc::compiler::serialize;
+use tnybsc::{repl, CompileError, Diagnostics, Dialect, Options, Program};
 
-pub mod compiler;
+/// Where built programs and their C code go unless told otherwise.
+const ARTIFACTS_DIR: &str = "./artifacts";
 
 /// A tiny basic compiler
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Execute code from the specified path
-    #[clap(value_parser)]
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Check a program for errors without building it
+    Check {
+        #[clap(flatten)]
+        source: SourceArgs,
+    },
+    /// Write the C code for a program to PATH, or to stdout if no path is given
+    Emit {
+        #[clap(flatten)]
+        source: SourceArgs,
+        /// File to write the C code to
+        #[clap(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Compile a program to an executable, by default at ./artifacts/<name>.out
+    Build {
+        #[clap(flatten)]
+        source: SourceArgs,
+        #[clap(flatten)]
+        toolchain: ToolchainArgs,
+        /// File to write the executable to; its C code is written beside it
+        #[clap(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Compile a program and run it
+    Run {
+        #[clap(flatten)]
+        source: SourceArgs,
+        #[clap(flatten)]
+        toolchain: ToolchainArgs,
+        /// Run the program directly with the built-in interpreter instead of compiling it to C
+        #[clap(short, long)]
+        interpret: bool,
+    },
+    /// Write the tokens of a program to PATH, or to stdout if no path is given
+    Lex {
+        #[clap(flatten)]
+        source: SourceArgs,
+        /// File to write the tokens to
+        #[clap(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Format to write the output in
+        #[clap(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+    },
+    /// Write the parsed form of a program to PATH, or to stdout if no path is given
+    Parse {
+        #[clap(flatten)]
+        source: SourceArgs,
+        /// File to write the parsed program to
+        #[clap(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Format to write the output in
+        #[clap(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+    },
+    /// Start an interactive session that runs statements as they are entered
+    Repl,
+}
+
+/// Where a program comes from, and how to read it.
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    /// Read the program from the specified path
+    #[clap(value_parser, required_unless_present = "code")]
     source_path: Option<PathBuf>,
 
-    /// Execute code directly from the command line
-    #[clap(short, long)]
+    /// Take the program directly from the command line
+    #[clap(short, long, conflicts_with = "source_path")]
     code: Option<String>,
 
-    /// Print lexical output to LEX_PATH or STDOUT if path not supplied.
-    #[clap(short, long, id = "LEX_PATH")]
-    lex: Option<Option<PathBuf>>,
+    /// Treat a number at the start of a line as its label, as in `10 PRINT "HI"` / `20 GOTO 10`
+    #[clap(short = 'n', long)]
+    line_numbers: bool,
+}
 
-    /// Print parser output to PARSE_PATH or STDOUT if path not supplied.
-    #[clap(short, long, id = "PARSE_PATH")]
-    parse: Option<Option<PathBuf>>,
+/// How `lex` and `parse` write their output. See the README for the schema.
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum OutputFormat {
+    Json,
+    /// Compact S-expressions
+    Sexp,
+}
 
-    /// Print compiler output to COMPILE_PATH or STDOUT if path not supplied.
-    #[clap(long, id = "COMPILE_PATH")]
-    compile: Option<Option<PathBuf>>,
+impl From<OutputFormat> for serialize::Format {
+    fn from(format: OutputFormat) -> serialize::Format {
+        match format {
+            OutputFormat::Json => serialize::Format::Json,
+            OutputFormat::Sexp => serialize::Format::Sexp,
+        }
+    }
 }
 
-fn main() {
-    let args = Args::parse();
+/// How to turn the emitted C into an executable.
+#[derive(clap::Args, Debug)]
+struct ToolchainArgs {
+    /// C compiler to build with [default: $CC, or the first of cc, gcc, clang and tcc found]
+    #[clap(long)]
+    cc: Option<String>,
 
-    let program_name: String;
-    let source_code = if let Some(code) = args.code {
-        // add newline automatically
-        let mut source = code;
-        source.push('\n');
-        program_name = "terminal_commands".to_string();
-        source
-    } else if let Some(source_path) = args.source_path {
-        program_name = source_path
-            .file_stem()
-            .expect("file name was passed!")
-            .to_os_string()
-            .into_string()
-            .unwrap();
-        match std::fs::read_to_string(source_path) {
-            Ok(file) => file,
-            Err(err) => panic!("could not open file: {:?}", err),
+    /// Optimization level to pass to the C compiler, e.g. 2 for -O2
+    #[clap(short = 'O', value_name = "LEVEL")]
+    optimize: Option<String>,
+
+    /// Extra flag to pass to the C compiler, after any in $CFLAGS; may be repeated
+    #[clap(long = "cflag", value_name = "FLAG", allow_hyphen_values = true)]
+    cflags: Vec<String>,
+}
+
+impl ToolchainArgs {
+    fn apply(self, mut options: Options) -> Options {
+        if let Some(cc) = self.cc {
+            options = options.cc(cc);
         }
-    } else {
-        Args::command().print_help().unwrap();
-        std::process::exit(1);
-    };
+        if let Some(level) = self.optimize {
+            options = options.optimize(level);
+        }
+        self.cflags.into_iter().fold(options, Options::cflag)
+    }
+}
 
-    let tokens = lexer::lex_source(&source_code);
-    if let Some(lex_opt) = args.lex {
-        if let Some(_lex_path) = lex_opt {
-            todo!("output of scanned code to file not implemented")
-        } else {
-            dbg!(&tokens);
+/// A program's code, with the names used to report errors in it and to name what is built
+/// from it.
+struct Source {
+    program_name: String,
+    file_name: String,
+    code: String,
+    options: Options,
+}
+
+impl Source {
+    fn load(args: SourceArgs) -> Source {
+        let options = match args.line_numbers {
+            true => Options::new().dialect(Dialect::LineNumbers),
+            false => Options::new(),
+        };
+        if let Some(mut code) = args.code {
+            // add newline automatically
+            code.push('\n');
+            return Source {
+                program_name: "terminal_commands".to_string(),
+                file_name: "<code>".to_string(),
+                code,
+                options,
+            };
+        }
+        let source_path = args
+            .source_path
+            .expect("clap requires a path without --code");
+        let program_name = source_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "program".to_string());
+        let file_name = source_path.display().to_string();
+        match fs::read_to_string(&source_path) {
+            Ok(code) => Source {
+                program_name,
+                file_name,
+                code,
+                options,
+            },
+            Err(err) => {
+                eprintln!("error: could not open {file_name}: {err}");
+                std::process::exit(1);
+            }
         }
     }
-    let mut prgm = parser::Program::new();
-    match prgm.build(tokens.unwrap()) {
-        Ok(()) => {}
-        Err(err) => panic!("{:?}", err),
+
+    fn lex(&self) -> Vec<SpannedToken> {
+        tnybsc::lex(&self.code).unwrap_or_else(|diagnostics| self.exit_with(&diagnostics))
     }
-    if let Some(parse_opt) = args.parse {
-        if let Some(_parse_path) = parse_opt {
-            todo!("output of parsed code to file not implemented")
-        } else {
-            dbg!(&prgm);
+
+    /// Parses and checks the program, reporting any warnings.
+    fn parse(&self) -> Program {
+        match self.options.parse(&self.code) {
+            Ok(program) => {
+                for warning in &program.warnings {
+                    eprint!("{}", warning.render(&self.file_name, &self.code));
+                }
+                program
+            }
+            Err(diagnostics) => self.exit_with(&diagnostics),
         }
     }
-    let mut emitter = emitter::Emitter::new();
-    emitter.build(prgm);
 
-    let mut compile_path = PathBuf::from("./artifacts");
-    compile_path.push(&program_name);
+    /// Compiles the program to an executable at `output`, or in the artifacts directory,
+    /// and returns its path. The C code is kept beside it.
+    fn build(&self, toolchain: ToolchainArgs, output: Option<PathBuf>) -> PathBuf {
+        let build_path = output
+            .unwrap_or_else(|| Path::new(ARTIFACTS_DIR).join(format!("{}.out", self.program_name)));
+        let options = toolchain.apply(self.options.clone());
+        self.or_exit(options.build(self.parse(), &build_path));
+        build_path
+    }
 
-    if let Some(compile_opt) = args.compile {
-        if let Some(compile_opt_arg) = compile_opt {
-            compile_path = PathBuf::from(compile_opt_arg);
-        } else {
-            print!("{}", emitter);
-        }
+    fn or_exit<T>(&self, result: Result<T, CompileError>) -> T {
+        result.unwrap_or_else(|err| self.exit_with_error(&err))
     }
-    let mut build_path = compile_path.clone();
-    compile_path.set_extension("c");
-    build_path.set_extension("out");
-    let mut compiled_file = match fs::File::create(&compile_path) {
-        Ok(path) => path,
-        Err(err) => panic!("unable to create {:?}: {err:?}", &compile_path),
-    };
-    match compiled_file.write_all(format!("{emitter}").as_bytes()) {
-        Ok(_) => {}
-        Err(err) => panic!("unable to write to {:?}: {err:?}", &compile_path),
-    };
 
-    let compile_command = Command::new("gcc")
-        .arg(compile_path)
-        .arg("-o")
-        .arg(&build_path)
-        .output();
-    match compile_command {
-        Ok(out) => {
-            for line in String::from_utf8(out.stdout).unwrap().lines() {
-                println!("{}", line);
+    /// Reports `err` on stderr, pointing into the code where possible, and exits.
+    fn exit_with_error(&self, err: &CompileError) -> ! {
+        self.exit_with(&err.clone().into())
+    }
+
+    fn exit_with(&self, diagnostics: &Diagnostics) -> ! {
+        eprint!("{}", diagnostics.render(&self.file_name, &self.code));
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    match args.command {
+        Subcommands::Check { source } => {
+            Source::load(source).parse();
+        }
+        Subcommands::Emit { source, output } => {
+            let source = Source::load(source);
+            let c_code = tnybsc::emit_c(source.parse());
+            source.or_exit(write_output(output.as_deref(), &c_code));
+        }
+        Subcommands::Build {
+            source,
+            toolchain,
+            output,
+        } => {
+            Source::load(source).build(toolchain, output);
+        }
+        Subcommands::Run {
+            source,
+            toolchain,
+            interpret,
+        } => {
+            let source = Source::load(source);
+            if interpret {
+                let program = source.parse();
+                let stdin = std::io::stdin();
+                let mut stdout = std::io::stdout();
+                let result = Interpreter::new().run(&program, &mut stdin.lock(), &mut stdout);
+                source.or_exit(result);
+                return;
             }
-            for line in String::from_utf8(out.stderr).unwrap().lines() {
-                println!("{}", line);
+            let build_path = source.build(toolchain, None);
+            // The program shares our stdin, stdout and stderr, so that INPUT reads from
+            // the terminal and output appears as it is printed.
+            source.or_exit(std::io::stdout().flush().map_err(CompileError::from));
+            // A bare file name would be looked up on the PATH.
+            let build_path = Path::new(".").join(build_path);
+            match Command::new(&build_path).status() {
+                Ok(status) => std::process::exit(exit_code(status)),
+                Err(err) => source.exit_with_error(&CompileError::backend(format!(
+                    "unable to run {}: {err}",
+                    build_path.display()
+                ))),
             }
         }
-        Err(err) => panic!("Error during compilation: {}", err),
-    };
-
-    let run_command = Command::new(build_path).output();
-    match run_command {
-        Ok(out) => {
-            for line in String::from_utf8(out.stdout).unwrap().lines() {
-                println!("{}", line);
+        Subcommands::Lex {
+            source,
+            output,
+            format,
+        } => {
+            let source = Source::load(source);
+            let tokens = serialize::tokens(&source.lex()).render(format.into());
+            source.or_exit(write_output(output.as_deref(), &format!("{tokens}\n")));
+        }
+        Subcommands::Parse {
+            source,
+            output,
+            format,
+        } => {
+            let source = Source::load(source);
+            let program = serialize::program(&source.parse()).render(format.into());
+            source.or_exit(write_output(output.as_deref(), &format!("{program}\n")));
+        }
+        Subcommands::Repl => {
+            let stdin = std::io::stdin();
+            let mut stdout = std::io::stdout();
+            if let Err(err) = repl::Repl::new().run(&mut stdin.lock(), &mut stdout) {
+                eprintln!("error: {err}");
+                std::process::exit(1);
             }
         }
-        Err(err) => panic!("Error during code execution: {}", err),
+    }
+}
+
+/// Writes `contents` to the file at `path`, creating any missing directories, or to stdout
+/// if there is no path.
+fn write_output(path: Option<&Path>, contents: &str) -> Result<(), CompileError> {
+    let Some(path) = path else {
+        let mut stdout = std::io::stdout();
+        return Ok(stdout.write_all(contents.as_bytes())?);
+    };
+    let write_error = |err: std::io::Error| {
+        CompileError::backend(format!("unable to write {}: {err}", path.display()))
     };
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    fs::write(path, contents).map_err(write_error)
+}
+
+/// The exit code to pass on for a program that finished with `status`. A program killed
+/// by a signal gets the shell's 128 plus the signal number.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+#[test]
+fn test_args() {
+    use clap::CommandFactory;
+    Args::command().debug_assert();
+
+    let args = Args::parse_from(["tnybsc", "build", "fib.tbsc", "-O2", "--cflag", "-Wall"]);
+    let Subcommands::Build {
+        source, toolchain, ..
+    } = args.command
+    else {
+        panic!("expected build, found {:?}", args.command);
+    };
+    assert_eq!(source.source_path, Some(PathBuf::from("fib.tbsc")));
+    assert_eq!(toolchain.optimize.as_deref(), Some("2"));
+    assert_eq!(toolchain.cflags, vec!["-Wall"]);
+
+    // A program comes from a path or from --code, not both.
+    assert!(Args::try_parse_from(["tnybsc", "check"]).is_err());
+    assert!(Args::try_parse_from(["tnybsc", "check", "fib.tbsc", "-c", "PRINT 1"]).is_err());
 }
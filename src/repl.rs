@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use crate::compiler::interpreter::Interpreter;
+use crate::compiler::lexer::{self, Token};
+use crate::compiler::parser::{Dialect, Function, Program};
+use crate::compiler::types::Type;
+
+const PROMPT: &str = "> ";
+const CONTINUE_PROMPT: &str = "... ";
+const FILE_NAME: &str = "<repl>";
+
+/// An interactive session in the style of the classic BASIC environments: statements run
+/// as soon as they are entered, and are remembered so that the whole session can be
+/// listed or run again.
+///
+/// A line starting with a number is instead stored as that line of a numbered program,
+/// replacing any line with the same number; a number on its own deletes the line. Once
+/// there is a numbered program, `LIST` and `RUN` work on it rather than on the session.
+///
+/// Besides statements, the session understands a few commands:
+///
+/// * `LIST` prints every statement entered so far
+/// * `RUN` clears all variables and runs the listing from the top
+/// * `NEW` forgets the listing and all variables
+/// * `CLEAR` resets all variables, but keeps the listing
+/// * `BYE` ends the session
+pub struct Repl {
+    /// Source of every chunk that parsed successfully, in the order it was entered.
+    listing: Vec<String>,
+    /// Numbered program lines, kept in order of their number.
+    lines: BTreeMap<i32, String>,
+    /// Variables declared so far, so later lines may use them.
+    symbols: HashSet<String>,
+    /// Types inferred for those variables so far, so later lines treat them the same.
+    types: HashMap<String, Type>,
+    /// Functions defined so far, so later lines may call them.
+    functions: Vec<Function>,
+    interpreter: Interpreter,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            listing: Vec::new(),
+            lines: BTreeMap::new(),
+            symbols: HashSet::new(),
+            types: HashMap::new(),
+            functions: Vec::new(),
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// Reads lines from `input` until it runs out or the user says `BYE`. `INPUT`
+    /// statements read from the same stream.
+    pub fn run(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<()> {
+        let mut chunk = String::new();
+        loop {
+            match chunk.is_empty() {
+                true => write!(output, "{PROMPT}")?,
+                false => write!(output, "{CONTINUE_PROMPT}")?,
+            }
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+
+            if chunk.is_empty() {
+                match line.trim().to_uppercase().as_str() {
+                    "" => continue,
+                    "LIST" => {
+                        write!(output, "{}", self.source())?;
+                        continue;
+                    }
+                    "RUN" => {
+                        self.run_listing(input, output)?;
+                        continue;
+                    }
+                    "NEW" => {
+                        self.listing.clear();
+                        self.lines.clear();
+                        self.symbols.clear();
+                        self.types.clear();
+                        self.functions.clear();
+                        self.interpreter = Interpreter::new();
+                        continue;
+                    }
+                    "CLEAR" => {
+                        self.interpreter.clear();
+                        continue;
+                    }
+                    "BYE" => return Ok(()),
+                    _ => {}
+                }
+                if let Some((number, statement)) = numbered_line(&line) {
+                    match statement.trim().is_empty() {
+                        true => self.lines.remove(&number),
+                        false => self.lines.insert(number, line),
+                    };
+                    continue;
+                }
+            }
+
+            // Keep reading until every IF, WHILE, FOR and FUNCTION in the chunk has been
+            // closed.
+            chunk.push_str(&line);
+            if open_blocks(&chunk) > 0 {
+                continue;
+            }
+            let source = std::mem::take(&mut chunk);
+            self.execute(source, input, output)?;
+        }
+    }
+
+    /// Parses and runs one chunk, keeping it in the listing if it was valid.
+    fn execute(
+        &mut self,
+        source: String,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let mut program = Program::new();
+        program.symbols = self.symbols.clone();
+        program.types = self.types.clone();
+        program.functions = self.functions.clone();
+        if !self.build(&mut program, &source, output)? {
+            return Ok(());
+        }
+        self.symbols = program.symbols.clone();
+        self.types = program.types.clone();
+        self.functions = program.functions.clone();
+        self.listing.push(source.clone());
+        if let Err(err) = self.interpreter.run(&program, input, output) {
+            write!(output, "{}", err.render(FILE_NAME, &source))?;
+        }
+        Ok(())
+    }
+
+    /// The numbered program if there is one, otherwise every chunk entered so far.
+    fn source(&self) -> String {
+        match self.lines.is_empty() {
+            true => self.listing.concat(),
+            false => self.lines.values().map(String::as_str).collect(),
+        }
+    }
+
+    fn run_listing(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<()> {
+        let source = self.source();
+        let mut program = Program::new();
+        if !self.lines.is_empty() {
+            program.dialect = Dialect::LineNumbers;
+        }
+        if !self.build(&mut program, &source, output)? {
+            return Ok(());
+        }
+        self.interpreter.clear();
+        if let Err(err) = self.interpreter.run(&program, input, output) {
+            write!(output, "{}", err.render(FILE_NAME, &source))?;
+        }
+        Ok(())
+    }
+
+    /// Lexes and parses `source` into `program`, reporting any errors to `output`.
+    /// Returns whether it succeeded.
+    fn build(
+        &self,
+        program: &mut Program,
+        source: &str,
+        output: &mut dyn Write,
+    ) -> io::Result<bool> {
+        let result = lexer::lex_source(source)
+            .map_err(|err| vec![err])
+            .and_then(|tokens| program.build(tokens));
+        match result {
+            Ok(()) => Ok(true),
+            Err(errors) => {
+                for err in errors {
+                    write!(output, "{}", err.render(FILE_NAME, source))?;
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Splits a line such as `10 PRINT "HI"` into its line number and the rest of the line.
+fn numbered_line(line: &str) -> Option<(i32, &str)> {
+    let line = line.trim_start();
+    let digits = line.find(|chr: char| !chr.is_ascii_digit())?;
+    let number = line[..digits].parse().ok()?;
+    Some((number, &line[digits..]))
+}
+
+/// Number of IF, WHILE, FOR and FUNCTION blocks in `source` still waiting for their
+/// ENDIF, ENDWHILE, NEXT or ENDFUNCTION.
+/// Source that doesn't lex counts as complete, so that its error gets reported.
+fn open_blocks(source: &str) -> usize {
+    let Ok(tokens) = lexer::lex_source(source) else {
+        return 0;
+    };
+    let mut depth: usize = 0;
+    for token in tokens {
+        match token.token {
+            Token::If | Token::While | Token::For | Token::Function => depth += 1,
+            Token::EndIf | Token::EndWhile | Token::Next | Token::EndFunction => {
+                depth = depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// Feeds `session` to a fresh REPL and returns everything it printed, minus the prompts.
+#[cfg(test)]
+fn run_session(session: &str) -> String {
+    let mut output = Vec::new();
+    Repl::new()
+        .run(&mut session.as_bytes(), &mut output)
+        .unwrap();
+    String::from_utf8(output)
+        .unwrap()
+        .replace(CONTINUE_PROMPT, "")
+        .replace(PROMPT, "")
+}
+
+#[test]
+fn test_symbols_persist() {
+    assert_eq!(run_session("LET a = 2\nLET b = a * 3\nPRINT b\n"), "6\n\n");
+}
+
+#[test]
+fn test_types_persist() {
+    assert_eq!(
+        run_session("LET x = 2.5\nLET y = x * 3\nPRINT y\nLET n = 7\nPRINT n / 2\n"),
+        "7.5\n3\n\n"
+    );
+}
+
+#[test]
+fn test_multiline_blocks() {
+    let session = "\
+LET i = 0
+WHILE i < 2 REPEAT
+IF i == 1 THEN
+PRINT \"one\"
+ENDIF
+LET i = i + 1
+ENDWHILE
+PRINT i
+";
+    assert_eq!(run_session(session), "one\n2\n\n");
+}
+
+#[test]
+fn test_commands() {
+    let session = "\
+LET a = 1
+PRINT a
+LET a = a + 1
+CLEAR
+PRINT a
+LIST
+RUN
+NEW
+LIST
+PRINT a
+BYE
+PRINT 5
+";
+    let output = run_session(session);
+    let expected_start = "\
+1
+0
+LET a = 1
+PRINT a
+LET a = a + 1
+PRINT a
+1
+2
+error: Undeclared identifier 'a'";
+    assert!(output.starts_with(expected_start), "{output}");
+    assert!(!output.contains("5\n"));
+}
+
+#[test]
+fn test_errors_are_not_listed() {
+    let output = run_session("LET a = 1 +\nLET b = 2\nLIST\n");
+    assert!(output.starts_with("error: Expected number, string, identifier or '('"));
+    assert!(output.ends_with("LET b = 2\n\n"));
+}
+
+#[test]
+fn test_input_shares_stream() {
+    assert_eq!(run_session("INPUT n\n42\nPRINT n * 2\n"), "84\n\n");
+}
+
+#[test]
+fn test_numbered_lines() {
+    let session = "\
+30 GOTO 10
+10 LET i = 1
+20 IF i < 3 THEN
+25 PRINT i
+27 LET i = i + 1
+28 GOTO 20
+29 ENDIF
+30
+LIST
+RUN
+";
+    assert_eq!(
+        run_session(session),
+        "\
+10 LET i = 1
+20 IF i < 3 THEN
+25 PRINT i
+27 LET i = i + 1
+28 GOTO 20
+29 ENDIF
+1
+2
+
+"
+    );
+}
+
+#[test]
+fn test_functions_persist() {
+    let session = "\
+FUNCTION double(x)
+RETURN x * 2
+ENDFUNCTION
+PRINT double(4)
+PRINT double(double(1.5))
+";
+    assert_eq!(run_session(session), "8\n6\n\n");
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::compiler::error::CompileError;
+
+/// Compilers tried, in order, when neither `--cc` nor `CC` says which to use.
+pub const COMPILERS: &[&str] = &["cc", "gcc", "clang", "tcc"];
+
+/// The C compiler that turns the emitted code into an executable, and the flags it is
+/// given.
+#[derive(Debug, PartialEq)]
+pub struct Toolchain {
+    pub cc: String,
+    pub flags: Vec<String>,
+}
+
+impl Toolchain {
+    /// Uses the compiler `cc` if given, then the one in `CC`, then the first of
+    /// `COMPILERS` that is installed. The flags are those in `CFLAGS`, then `-O<level>`,
+    /// then `extra_flags`.
+    pub fn new(
+        cc: Option<String>,
+        level: Option<String>,
+        extra_flags: Vec<String>,
+    ) -> Result<Toolchain, CompileError> {
+        Toolchain::configure(
+            cc,
+            level,
+            extra_flags,
+            |name| std::env::var(name).ok(),
+            is_installed,
+        )
+    }
+
+    /// Like `new`, looking up environment variables with `env` and checking whether a
+    /// compiler exists with `installed`.
+    fn configure(
+        cc: Option<String>,
+        level: Option<String>,
+        extra_flags: Vec<String>,
+        env: impl Fn(&str) -> Option<String>,
+        installed: impl Fn(&str) -> bool,
+    ) -> Result<Toolchain, CompileError> {
+        let cc = cc
+            .or_else(|| env("CC"))
+            .filter(|cc| !cc.trim().is_empty())
+            .or_else(|| {
+                COMPILERS
+                    .iter()
+                    .find(|cc| installed(cc))
+                    .map(|cc| cc.to_string())
+            })
+            .ok_or_else(|| {
+                CompileError::backend(format!(
+                    "no C compiler found; install one of {}, or name one with --cc or CC",
+                    COMPILERS.join(", ")
+                ))
+            })?;
+        // `CC` may carry arguments of its own, as in `CC="ccache gcc"`.
+        let mut words = cc.split_whitespace().map(str::to_string);
+        let cc = words.next().unwrap_or_default();
+        let mut flags: Vec<String> = words.collect();
+        if let Some(cflags) = env("CFLAGS") {
+            flags.extend(cflags.split_whitespace().map(str::to_string));
+        }
+        if let Some(level) = level {
+            flags.push(format!("-O{level}"));
+        }
+        flags.extend(extra_flags);
+        Ok(Toolchain { cc, flags })
+    }
+
+    /// Compiles the C file at `source` into an executable at `output`. The compiler's
+    /// messages go to stderr, leaving stdout to the program.
+    pub fn compile(&self, source: &Path, output: &Path) -> Result<(), CompileError> {
+        let status = Command::new(&self.cc)
+            .args(&self.flags)
+            .arg(source)
+            .arg("-o")
+            .arg(output)
+            .stdout(std::io::stderr())
+            .status()
+            .map_err(|err| {
+                CompileError::backend(format!("unable to run C compiler '{}': {err}", self.cc))
+            })?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(CompileError::backend(format!(
+                "C compiler '{}' failed to compile {} ({status})",
+                self.cc,
+                source.display()
+            ))),
+        }
+    }
+}
+
+/// Whether `program` can be found on the `PATH`.
+fn is_installed(program: &str) -> bool {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+    let file_name = format!("{program}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&paths).any(|dir| dir.join(&file_name).is_file())
+}
+
+#[test]
+fn test_configure() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+    let only_clang = |cc: &str| cc == "clang";
+
+    let toolchain = Toolchain::configure(None, None, vec![], env(&[]), only_clang).unwrap();
+    assert_eq!(toolchain.cc, "clang");
+    assert!(toolchain.flags.is_empty());
+
+    let vars = &[("CC", "ccache gcc"), ("CFLAGS", "-Wall  -g")];
+    let toolchain = Toolchain::configure(
+        None,
+        Some("2".to_string()),
+        vec!["-lm".to_string()],
+        env(vars),
+        only_clang,
+    )
+    .unwrap();
+    assert_eq!(toolchain.cc, "ccache");
+    assert_eq!(toolchain.flags, vec!["gcc", "-Wall", "-g", "-O2", "-lm"]);
+
+    // --cc beats CC.
+    let toolchain =
+        Toolchain::configure(Some("tcc".to_string()), None, vec![], env(vars), only_clang).unwrap();
+    assert_eq!(toolchain.cc, "tcc");
+
+    let err = Toolchain::configure(None, None, vec![], env(&[("CC", "")]), |_| false).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "no C compiler found; install one of cc, gcc, clang, tcc, or name one with --cc or CC"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_compile_failure() {
+    let toolchain = Toolchain {
+        cc: "false".to_string(),
+        flags: vec![],
+    };
+    let err = toolchain
+        .compile(Path::new("prog.c"), Path::new("prog.out"))
+        .unwrap_err();
+    assert!(err
+        .message()
+        .starts_with("C compiler 'false' failed to compile prog.c ("));
+}